@@ -1,108 +1,305 @@
-use crate::state::{Direction, Move, State, PLACER_MOVES, SLIDER_MOVES, INITIAL_STATE};
-use crate::Player;
+use crate::random::Random;
+use crate::rules::{self, GameRules, Standard};
+use crate::state::{self, Board, Direction, InvalidState, Move, Role, State};
+use crate::utils::{self, log};
+use crate::{EngineInfo, Player};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::cmp;
 use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Index, IndexMut};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use wasm_bindgen::prelude::*;
-use crate::utils::{self, log};
 
-type Grid = [[u8; 4]; 4];
+mod cache;
+mod perft;
 
-const TURNS_MOD: i32 = 64;
+pub use cache::CacheError;
+pub use perft::{perft, Divergence, PerftCounts};
 
-fn slide_up(g: &Grid) -> Option<Grid> {
-    let mut grid = [[0; 4]; 4];
-    for i in 0..4 {
-        let mut end = 0;
-        for j in 0..4 {
-            if g[j][i] != 0 {
-                if grid[end][i] == 0 {
-                    grid[end][i] = g[j][i];
-                } else if grid[end][i] == g[j][i] {
-                    grid[end][i] += 1;
-                    end += 1;
-                } else {
-                    end += 1;
-                    grid[end][i] = g[j][i];
-                }
-            }
+// largest board the search handles, e.g. 6x6 or 4x9. Keys are copied and
+// hashed a lot, so this is kept small.
+pub const MAX_CELLS: usize = 36;
+
+// The tiles of a board of up to MAX_CELLS cells, as SearchRules codes (0 for
+// empty), kept in a fixed array so keys stay Copy. grid[x] is row x, like
+// state::Board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Grid {
+    rows: u8,
+    cols: u8,
+    cells: [u8; MAX_CELLS],
+}
+
+impl Grid {
+    fn new(rows: usize, cols: usize) -> Grid {
+        assert!(
+            rows > 0 && cols > 0 && rows * cols <= MAX_CELLS,
+            "Board too large for the search"
+        );
+        Grid {
+            rows: rows as u8,
+            cols: cols as u8,
+            cells: [0; MAX_CELLS],
         }
     }
 
-    if grid == *g {
-        None
-    } else {
-        Some(grid)
+    fn rows(&self) -> usize {
+        self.rows as usize
+    }
+
+    fn cols(&self) -> usize {
+        self.cols as usize
+    }
+
+    // the used part of cells, row-major
+    fn cells(&self) -> &[u8] {
+        &self.cells[..self.rows() * self.cols()]
     }
 }
 
-fn slide_down(g: &Grid) -> Option<Grid> {
-    let mut grid = [[0; 4]; 4];
-    for i in 0..4 {
-        let mut end = 0;
-        for j in 0..4 {
-            if g[3 - j][i] != 0 {
-                if grid[3 - end][i] == 0 {
-                    grid[3 - end][i] = g[3 - j][i];
-                } else if grid[3 - end][i] == g[3 - j][i] {
-                    grid[3 - end][i] += 1;
-                    end += 1;
-                } else {
-                    end += 1;
-                    grid[3 - end][i] = g[3 - j][i];
-                }
-            }
-        }
+// unused cells are always 0, so only the used ones are hashed
+impl Hash for Grid {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rows.hash(state);
+        self.cols.hash(state);
+        self.cells().hash(state);
     }
+}
 
-    if grid == *g {
-        None
-    } else {
-        Some(grid)
+impl Index<usize> for Grid {
+    type Output = [u8];
+
+    fn index(&self, x: usize) -> &[u8] {
+        let cols = self.cols();
+        &self.cells[x * cols..(x + 1) * cols]
     }
 }
 
-fn slide_left(g: &Grid) -> Option<Grid> {
-    let mut grid = [[0; 4]; 4];
-    for i in 0..4 {
-        let mut end = 0;
-        for j in 0..4 {
-            if g[i][j] != 0 {
-                if grid[i][end] == 0 {
-                    grid[i][end] = g[i][j];
-                } else if grid[i][end] == g[i][j] {
-                    grid[i][end] += 1;
-                    end += 1;
-                } else {
-                    end += 1;
-                    grid[i][end] = g[i][j];
+impl IndexMut<usize> for Grid {
+    fn index_mut(&mut self, x: usize) -> &mut [u8] {
+        let cols = self.cols();
+        &mut self.cells[x * cols..(x + 1) * cols]
+    }
+}
+
+#[cfg(test)]
+impl<const R: usize, const C: usize> From<[[u8; C]; R]> for Grid {
+    fn from(rows: [[u8; C]; R]) -> Grid {
+        let mut grid = Grid::new(R, C);
+        for x in 0..R {
+            grid[x].copy_from_slice(&rows[x]);
+        }
+        grid
+    }
+}
+
+const TURNS_MOD: i32 = 64;
+
+// node maps only hold TURNS_MOD consecutive turns
+const MAX_SEARCH_DEPTH: i32 = TURNS_MOD - 1;
+
+// The game's rules as the search applies them. Tiles are stored as codes:
+// their index in rules::tile_values plus one, which is log2 of the tile under
+// the standard rules, and merges are looked up in a table of codes. Walls
+// take the code after the tiles', which merges with nothing.
+//
+// Turns are counted by the tile sum (see key_from_state), so the search
+// assumes merges keep the sum, as they do when tiles merge into their sum.
+// A wall counts like a tile of one unit.
+#[derive(Debug)]
+struct SearchRules {
+    rules: Arc<dyn GameRules>,
+    // the tile of code c is values[c - 1]
+    values: Vec<i32>,
+    // the greatest common divisor of the spawns, which divides every tile
+    unit: i32,
+    placer_moves: Vec<Move>,
+    // see GameRules::one_step
+    one_step: bool,
+    // the slides allowed in each phase, see GameRules::phases
+    slider_moves: Vec<Vec<Move>>,
+    // the indices into symmetries() of the transforms that keep the rules,
+    // see rules::symmetries
+    symmetries: Vec<usize>,
+    // the code of a wall, values.len() + 1
+    wall: u8,
+    // the most walls a board can hold, blocked cells included
+    max_walls: usize,
+    // see GameRules::drops
+    drops: usize,
+    // the code of GameRules::target
+    target: Option<u8>,
+    // the code codes a and b merge into at a * (wall + 1) + b, 0 if they
+    // don't merge
+    merges: Vec<u8>,
+}
+
+impl SearchRules {
+    // Panics if the board or the tile set is too large for the search
+    fn new(rules: Arc<dyn GameRules>) -> SearchRules {
+        assert!(
+            rules.rows() * rules.cols() <= MAX_CELLS,
+            "Board too large for the search"
+        );
+        let values = rules::tile_values(&*rules);
+        assert!(values.len() <= rules::MAX_TILE_VALUES, "Too many tiles");
+        let wall = values.len() + 1;
+        let n = wall + 1;
+        let mut merges = vec![0; n * n];
+        for a in 1..wall {
+            for b in 1..wall {
+                if let Some(merged) = rules.merge(values[a - 1], values[b - 1]) {
+                    if let Ok(i) = values.binary_search(&merged) {
+                        merges[a * n + b] = (i + 1) as u8;
+                    }
                 }
             }
         }
+        let gcd = |mut a: i32, mut b: i32| {
+            while b != 0 {
+                (a, b) = (b, a % b);
+            }
+            a
+        };
+        let unit = rules.spawns().iter().fold(0, |g, &val| gcd(g, val));
+        SearchRules {
+            placer_moves: rules.placer_moves(),
+            slider_moves: (0..rules.phases())
+                .map(|phase| {
+                    rules
+                        .directions(phase)
+                        .iter()
+                        .map(|&d| Move::Slide(d))
+                        .collect()
+                })
+                .collect(),
+            symmetries: rules::symmetries(&*rules),
+            one_step: rules.one_step(),
+            wall: wall as u8,
+            max_walls: rules.blocked().len() + rules.walls(),
+            drops: rules.drops(),
+            target: rules
+                .target()
+                .and_then(|t| values.binary_search(&t).ok())
+                .map(|i| (i + 1) as u8),
+            rules,
+            values,
+            unit,
+            merges,
+        }
     }
 
-    if grid == *g {
-        None
-    } else {
-        Some(grid)
+    // what a tile of value val adds to the turn count
+    fn weight(&self, val: i32) -> i32 {
+        match val {
+            state::WALL => 2,
+            _ => 2 * val / self.unit,
+        }
+    }
+
+    fn code(&self, val: i32) -> Option<u8> {
+        match val {
+            0 => Some(0),
+            state::WALL => Some(self.wall),
+            _ => self.values.binary_search(&val).ok().map(|i| (i + 1) as u8),
+        }
+    }
+
+    fn value(&self, code: u8) -> i32 {
+        match code {
+            0 => 0,
+            _ if code == self.wall => state::WALL,
+            _ => self.values[code as usize - 1],
+        }
+    }
+
+    fn merge(&self, a: u8, b: u8) -> u8 {
+        self.merges[a as usize * (self.wall as usize + 1) + b as usize]
+    }
+
+    // whether g holds the target tile or a larger one, see State::won
+    fn won(&self, g: &Grid) -> bool {
+        self.target
+            .is_some_and(|t| g.cells().iter().any(|&c| c >= t && c != self.wall))
+    }
+
+    // whether these are the rules self was made from
+    fn same(&self, rules: &dyn GameRules) -> bool {
+        self.rules.name() == rules.name()
+            && (self.rules.rows(), self.rules.cols()) == (rules.rows(), rules.cols())
     }
 }
 
-fn slide_right(g: &Grid) -> Option<Grid> {
-    let mut grid = [[0; 4]; 4];
-    for i in 0..4 {
-        let mut end = 0;
-        for j in 0..4 {
-            if g[i][3 - j] != 0 {
-                if grid[i][3 - end] == 0 {
-                    grid[i][3 - end] = g[i][3 - j];
-                } else if grid[i][3 - end] == g[i][3 - j] {
-                    grid[i][3 - end] += 1;
-                    end += 1;
+// Slides every line of g towards the edge d points at. Lines are walked
+// from that edge inwards, as (start, step) offsets into the cells array.
+fn slide(g: &Grid, d: Direction, r: &SearchRules) -> Option<Grid> {
+    let (rows, cols) = (g.rows() as isize, g.cols() as isize);
+    let (lines, len, line_step, step, first) = match d {
+        Direction::Up => (cols, rows, 1, cols, 0),
+        Direction::Down => (cols, rows, 1, -cols, (rows - 1) * cols),
+        Direction::Left => (rows, cols, cols, 1, 0),
+        Direction::Right => (rows, cols, cols, -1, cols - 1),
+    };
+    let mut grid = Grid::new(g.rows(), g.cols());
+    for line in 0..lines {
+        let start = first + line * line_step;
+        let cell = |k: isize| (start + k * step) as usize;
+        if r.one_step {
+            // the first tile that can move or merge takes the rest of the
+            // line up to the next wall with it, one cell towards the edge
+            let mut shifted = false;
+            for k in 0..len {
+                let val = g.cells[cell(k)];
+                if val == r.wall {
+                    grid.cells[cell(k)] = val;
+                    shifted = false;
+                } else if shifted {
+                    grid.cells[cell(k - 1)] = val;
+                } else if k > 0 && val != 0 {
+                    let prev = g.cells[cell(k - 1)];
+                    if prev == 0 {
+                        grid.cells[cell(k - 1)] = val;
+                        shifted = true;
+                    } else if r.merge(prev, val) != 0 {
+                        grid.cells[cell(k - 1)] = r.merge(prev, val);
+                        shifted = true;
+                    } else {
+                        grid.cells[cell(k)] = val;
+                    }
                 } else {
-                    end += 1;
-                    grid[i][3 - end] = g[i][3 - j];
+                    grid.cells[cell(k)] = val;
                 }
             }
+            continue;
+        }
+        let mut end = 0;
+        for k in 0..len {
+            let val = g.cells[cell(k)];
+            if val == 0 {
+                continue;
+            }
+            // tiles behind a wall slide up to it
+            if val == r.wall {
+                grid.cells[cell(k)] = val;
+                end = k + 1;
+                continue;
+            }
+            let merged = r.merge(grid.cells[cell(end)], val);
+            if grid.cells[cell(end)] == 0 {
+                grid.cells[cell(end)] = val;
+            } else if merged != 0 {
+                grid.cells[cell(end)] = merged;
+                end += 1;
+            } else {
+                end += 1;
+                grid.cells[cell(end)] = val;
+            }
         }
     }
 
@@ -114,8 +311,8 @@ fn slide_right(g: &Grid) -> Option<Grid> {
 }
 
 fn place(g: &Grid, x: usize, y: usize, val: u8) -> Option<Grid> {
-    if g[x][y] == 0 {
-        let mut grid = g.clone();
+    if x < g.rows() && y < g.cols() && g[x][y] == 0 {
+        let mut grid = *g;
         grid[x][y] = val;
         Some(grid)
     } else {
@@ -127,6 +324,10 @@ fn place(g: &Grid, x: usize, y: usize, val: u8) -> Option<Grid> {
 pub struct NodeKey {
     turns: i32,
     grid: Grid, // TODO: replace this with hash
+    // see GameRules::phases, 0 unless the rules have a schedule
+    phase: u8,
+    // see State::placed
+    placed: u8,
 }
 
 #[derive(Debug)]
@@ -154,24 +355,114 @@ struct NodeData {
 pub struct Ai {
     // index = depth (root depth is 0)
     // even depth -> Placer, odd depth -> Slider
-    // also encodes symmetry: 8 keys map to the same node
+    // also encodes symmetry: up to 8 keys map to the same node
     sym_map: Vec<HashMap<Grid, Grid>>,
-    node_map: Vec<HashMap<Grid, NodeData>>,
+    // keyed by the canonical grid, the phase and the tiles placed this turn
+    node_map: Vec<HashMap<(Grid, u8, u8), NodeData>>,
+    rules: SearchRules,
     root_key: NodeKey,
     search_depth: i32,
+    difficulty: Difficulty,
+    time_limit: Option<Duration>,
+    // search result of the last pick_move, if it searched
+    last_info: Option<EngineInfo>,
+    rng: StdRng,
+    // placements made so far, for Difficulty::EveryKth
+    spawns: u32,
+    // when a timed search has to give up, see deepen
+    deadline: Option<Instant>,
+    // nodes entered, to look at the clock every so often
+    nodes: u64,
+    // the deadline passed, and values since are meaningless
+    aborted: bool,
+    // what random placements place, empty for the smallest spawn of the
+    // rules, see Random::with_spawns
+    spawn_weights: Vec<(i32, f64)>,
+}
+
+/// How hard the Ai tries when it is the Placer. The search depth is set
+/// separately; the Slider always plays its best move, which under Mixed is
+/// the best one against such a Placer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Difficulty {
+    // always the best move found by the search
+    Adversarial,
+    // sample root moves with probability proportional to exp(value / T)
+    Softmax(f64),
+    // a uniformly random placement with probability epsilon
+    Epsilon(f64),
+    // adversarial on every k-th placement, random otherwise
+    EveryKth(u32),
+    // adversarial with probability p, random otherwise. Unlike the others
+    // the search expects this of the Placer too, on either side: a
+    // placement is worth p times the best one plus 1 - p times the average
+    // of the random ones, so 0 is expectimax against a random Placer and 1
+    // is plain minimax.
+    Mixed(f64),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidDifficulty;
+
+// "adversarial", "softmax:<temperature>", "epsilon:<probability>",
+// "every:<k>" or "mixed:<probability>"
+impl FromStr for Difficulty {
+    type Err = InvalidDifficulty;
+
+    fn from_str(s: &str) -> Result<Difficulty, InvalidDifficulty> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+        let difficulty = match (name, param) {
+            ("adversarial", None) => Difficulty::Adversarial,
+            ("softmax", Some(t)) => Difficulty::Softmax(t.parse().map_err(|_| InvalidDifficulty)?),
+            ("epsilon", Some(e)) => Difficulty::Epsilon(e.parse().map_err(|_| InvalidDifficulty)?),
+            ("every", Some(k)) => Difficulty::EveryKth(k.parse().map_err(|_| InvalidDifficulty)?),
+            ("mixed", Some(p)) => Difficulty::Mixed(p.parse().map_err(|_| InvalidDifficulty)?),
+            _ => return Err(InvalidDifficulty),
+        };
+        match difficulty {
+            Difficulty::Softmax(t) if t.is_nan() || t <= 0.0 => Err(InvalidDifficulty),
+            Difficulty::Epsilon(e) | Difficulty::Mixed(e) if !(0.0..=1.0).contains(&e) => {
+                Err(InvalidDifficulty)
+            }
+            Difficulty::EveryKth(0) => Err(InvalidDifficulty),
+            _ => Ok(difficulty),
+        }
+    }
+}
+
+// the notation FromStr accepts
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difficulty::Adversarial => write!(f, "adversarial"),
+            Difficulty::Softmax(t) => write!(f, "softmax:{t}"),
+            Difficulty::Epsilon(e) => write!(f, "epsilon:{e}"),
+            Difficulty::EveryKth(k) => write!(f, "every:{k}"),
+            Difficulty::Mixed(p) => write!(f, "mixed:{p}"),
+        }
+    }
 }
 
 impl Ai {
     fn key_to_node(&mut self, key: NodeKey) -> (NodeKey, &mut NodeData) {
-        let NodeKey { turns, grid } = key;
+        let NodeKey {
+            turns,
+            grid,
+            phase,
+            placed,
+        } = key;
         let idx = (turns % TURNS_MOD) as usize;
         let max_grid = match self.sym_map[idx].get(&grid) {
             Some(&g) => g,
             None => {
                 // TODO: change max to include 0,0 + 0,1
                 let new_grids = symmetries(&grid);
-                let max_grid = *new_grids.iter().max().unwrap();
-                for flipped_grid in new_grids {
+                let kept = || self.rules.symmetries.iter().map(|&t| new_grids[t]);
+                let max_grid = kept().max().unwrap();
+                for flipped_grid in kept() {
                     self.sym_map[idx].insert(flipped_grid, max_grid);
                 }
                 max_grid
@@ -180,14 +471,20 @@ impl Ai {
         let flipped_key = NodeKey {
             turns,
             grid: max_grid,
+            phase,
+            placed,
         };
         let node = self.node_map[idx]
-            .entry(max_grid)
-            .or_insert_with(|| new_node(&flipped_key));
+            .entry((max_grid, phase, placed))
+            .or_insert_with(|| new_node(&flipped_key, &self.rules));
         (flipped_key, node)
     }
 
     fn negamax(&mut self, key: NodeKey, max_depth: i32, alpha: i32, beta: i32) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+        let p = self.adversary();
         let (key, node) = self.key_to_node(key);
         let mut a = alpha;
         let mut b = beta;
@@ -214,19 +511,27 @@ impl Ai {
         if key.turns >= max_depth {
             // TODO: optimize leaf case
             let sign = 2 * (key.turns % 2) - 1;
-            let value = sign * heuristic(&key.grid);
+            let value = sign * heuristic(&key.grid, &self.rules);
+            let idx = (key.turns % TURNS_MOD) as usize;
+            let node = self.node_map[idx]
+                .get_mut(&(key.grid, key.phase, key.placed))
+                .unwrap();
             node.upper_bound = value;
             node.lower_bound = value;
             node.search_depth = key.turns;
             return value;
         }
 
+        if p < 1.0 && key.turns % 2 == 0 {
+            return self.mixed_value(key, max_depth, p);
+        }
+
         let mut value = i32::MIN;
         // TODO: use children.enumerate to save bext move? (or save ordering)
         let mut best_child = None;
         // TODO: try let vec: &Vec = &node.children?
         for child_key in node.children.clone() {
-            let v = -self.negamax(child_key, max_depth, -b, -a);
+            let v = self.child_value(&key, child_key, max_depth, a, b);
             if v > value {
                 best_child = Some(child_key);
                 value = v;
@@ -237,8 +542,14 @@ impl Ai {
             }
         }
 
+        // nothing is stored from an unfinished search
+        if self.aborted {
+            return value;
+        }
         let idx = (key.turns % TURNS_MOD) as usize;
-        let node = self.node_map[idx].get_mut(&key.grid).unwrap();
+        let node = self.node_map[idx]
+            .get_mut(&(key.grid, key.phase, key.placed))
+            .unwrap();
 
         // 3 cases: v in (-infty, a], (a, b), or [b, +infty)
         // Set upper bound, both bounds, or lower bound in respective cases
@@ -254,27 +565,241 @@ impl Ai {
         value
     }
 
-    fn best_root_move(&mut self) -> Move {
-        let moves: &[Move] = if self.root_key.turns % 2 == 0 {
-            &PLACER_MOVES
+    // The value of a Placer node under Difficulty::Mixed(p), from the
+    // Placer's point of view. Averages need exact child values, so there is
+    // no pruning here and the value stored is exact.
+    fn mixed_value(&mut self, key: NodeKey, max_depth: i32, p: f64) -> i32 {
+        let mut best = i32::MIN;
+        let mut best_child = None;
+        let (mut sum, mut total) = (0.0, 0.0);
+        for m in moves_for(&key, &self.rules).to_vec() {
+            let Some(child) = apply_move(&key, m, &self.rules) else {
+                continue;
+            };
+            let v = self.child_value(&key, child, max_depth, -i32::MAX, i32::MAX);
+            if v > best {
+                best = v;
+                best_child = Some(child);
+            }
+            // random placements are spread evenly over the empty cells
+            if let Move::Place { val, .. } = m {
+                let w = self.spawn_weight(val);
+                sum += w * v as f64;
+                total += w;
+            }
+        }
+        let value = if best_child.is_none() || total == 0.0 {
+            best
         } else {
-            &SLIDER_MOVES
+            (p * best as f64 + (1.0 - p) * sum / total).round() as i32
         };
+        if self.aborted {
+            return value;
+        }
 
+        let idx = (key.turns % TURNS_MOD) as usize;
+        let node = self.node_map[idx]
+            .get_mut(&(key.grid, key.phase, key.placed))
+            .unwrap();
+        node.upper_bound = value;
+        node.lower_bound = value;
+        node.search_depth = max_depth;
+        node.best_child = best_child;
+        value
+    }
+
+    // Whether the deadline has passed, looking at the clock every 1024 nodes
+    fn out_of_time(&mut self) -> bool {
+        if let Some(deadline) = self.deadline {
+            self.nodes += 1;
+            if self.nodes.is_multiple_of(1024) && Instant::now() >= deadline {
+                self.aborted = true;
+            }
+        }
+        self.aborted
+    }
+
+    // One iteration of iterative deepening from key, None if it ran past
+    // deadline. The tables then still hold the last completed iteration's
+    // results at key.
+    fn deepen(&mut self, key: NodeKey, max_depth: i32, deadline: Option<Instant>) -> Option<i32> {
+        self.deadline = deadline;
+        let value = self.negamax(key, max_depth, -i32::MAX, i32::MAX);
+        self.deadline = None;
+        (!std::mem::take(&mut self.aborted)).then_some(value)
+    }
+
+    // The value of child from the point of view of whoever moves at key,
+    // which is the same side again between the drops of a Placer's turn
+    fn child_value(
+        &mut self,
+        key: &NodeKey,
+        child: NodeKey,
+        max_depth: i32,
+        a: i32,
+        b: i32,
+    ) -> i32 {
+        if child.turns % 2 == key.turns % 2 {
+            self.negamax(child, max_depth, a, b)
+        } else {
+            -self.negamax(child, max_depth, -b, -a)
+        }
+    }
+
+    fn best_root_move(&mut self) -> Move {
         let (_, root_node) = self.key_to_node(self.root_key);
         let best_child = root_node.best_child.unwrap();
-        let (best_child_flipped, _) = self.key_to_node(best_child);
-
-        let mut best_move = moves[0];
-        for m in moves {
-            if let Some(key) = apply_move(&self.root_key, *m) {
-                let (key, _) = self.key_to_node(key);
-                if key == best_child_flipped {
-                    best_move = *m;
+        self.find_move(self.root_key, best_child).unwrap().0
+    }
+
+    // Node children are generated from the canonical (flipped) grid, so the
+    // move leading to a stored child is only known up to symmetry. Find the
+    // move from `key` that reaches a position symmetric to `target`, along
+    // with the resulting unflipped key.
+    fn find_move(&mut self, key: NodeKey, target: NodeKey) -> Option<(Move, NodeKey)> {
+        let (target_flipped, _) = self.key_to_node(target);
+        for m in moves_for(&key, &self.rules).to_vec() {
+            if let Some(child) = apply_move(&key, m, &self.rules) {
+                let (child_flipped, _) = self.key_to_node(child);
+                if child_flipped == target_flipped {
+                    return Some((m, child));
+                }
+            }
+        }
+        None
+    }
+
+    // Follow stored best children from `key` to build a principal variation,
+    // stopping at the search horizon
+    fn principal_variation(&mut self, key: NodeKey, max_depth: i32) -> Vec<Move> {
+        let mut pv = Vec::new();
+        let mut key = key;
+        while key.turns < max_depth {
+            let (_, node) = self.key_to_node(key);
+            let best_child = match node.best_child {
+                Some(child) => child,
+                None => break,
+            };
+            match self.find_move(key, best_child) {
+                Some((m, child)) => {
+                    pv.push(m);
+                    key = child;
                 }
+                None => break,
+            }
+        }
+        pv
+    }
+
+    /// Best move for whichever side is to move in `s`, independent of the
+    /// game followed through `update_move`. Ok(None) means the side to move
+    /// has no legal move.
+    pub fn best_move(&mut self, s: &State) -> Result<Option<Move>, InvalidState> {
+        s.check()?;
+        self.use_rules(s.rules());
+        let key = key_from_state(s, &self.rules);
+        let max_depth = key.turns + self.search_depth;
+        self.negamax(key, max_depth, -i32::MAX, i32::MAX);
+        let (_, node) = self.key_to_node(key);
+        Ok(match node.best_child {
+            Some(child) => self.find_move(key, child).map(|(m, _)| m),
+            None => None,
+        })
+    }
+
+    /// Iterative deepening from `s` to at most `depth` plies, stopping
+    /// early when `limit` is half used up or the result is a proven death
+    /// or win. An iteration still running when `limit` is up is abandoned
+    /// for the last completed one.
+    /// `report` gets the result of every iteration. Ok(None) means the side
+    /// to move has no legal move.
+    pub fn search(
+        &mut self,
+        s: &State,
+        depth: i32,
+        limit: Option<Duration>,
+        mut report: impl FnMut(&EngineInfo),
+    ) -> Result<Option<EngineInfo>, InvalidState> {
+        s.check()?;
+        self.use_rules(s.rules());
+        let key = key_from_state(s, &self.rules);
+        let start = Instant::now();
+        let mut info = None;
+        for d in 1..=depth.clamp(1, MAX_SEARCH_DEPTH) {
+            // the first iteration always finishes, so there is a move
+            let deadline = limit.filter(|_| d > 1).map(|l| start + l);
+            let Some(value) = self.deepen(key, key.turns + d, deadline) else {
+                break;
+            };
+            let pv = self.principal_variation(key, key.turns + d);
+            if pv.is_empty() {
+                return Ok(None);
+            }
+            let iteration = EngineInfo {
+                depth: d,
+                value,
+                pv,
+            };
+            report(&iteration);
+            info = Some(iteration);
+            if self.proven(value) || limit.is_some_and(|l| start.elapsed() * 2 >= l) {
+                break;
+            }
+        }
+        Ok(info)
+    }
+
+    /// Searches every legal move from `s` to `depth` plies with a full
+    /// window, returning them sorted best-first from the mover's point of
+    /// view.
+    pub fn analyze(&mut self, s: &State, depth: i32) -> Result<Vec<RootMove>, InvalidState> {
+        s.check()?;
+        self.use_rules(s.rules());
+        let key = key_from_state(s, &self.rules);
+        Ok(self.analyze_key(key, depth.clamp(1, MAX_SEARCH_DEPTH)))
+    }
+
+    // Searches under the given rules from now on. The tables only hold
+    // positions of one game variant, so they're cleared when it changes.
+    fn use_rules(&mut self, rules: &Arc<dyn GameRules>) {
+        if !self.rules.same(&**rules) {
+            self.rules = SearchRules::new(rules.clone());
+            self.clear();
+        }
+    }
+
+    fn clear(&mut self) {
+        for i in 0..TURNS_MOD as usize {
+            self.sym_map[i].clear();
+            self.node_map[i].clear();
+        }
+    }
+
+    fn analyze_key(&mut self, root_key: NodeKey, depth: i32) -> Vec<RootMove> {
+        let max_depth = root_key.turns + depth;
+        let mut root_moves = Vec::new();
+        for m in moves_for(&root_key, &self.rules).to_vec() {
+            if let Some(child) = apply_move(&root_key, m, &self.rules) {
+                let value = self.child_value(&root_key, child, max_depth, -i32::MAX, i32::MAX);
+                let mut pv = vec![m];
+                pv.extend(self.principal_variation(child, max_depth));
+                // the Slider's point of view
+                let slider_value = if root_key.turns % 2 == 1 {
+                    value
+                } else {
+                    -value
+                };
+                root_moves.push(RootMove {
+                    m,
+                    value,
+                    proven_death: self.proven(value) && slider_value < 0,
+                    proven_win: self.proven(value) && slider_value > 0,
+                    pv,
+                });
             }
         }
-        best_move
+        root_moves.sort_by_key(|rm| cmp::Reverse(rm.value));
+        root_moves
     }
 
     pub fn print_node(&mut self, key: NodeKey) {
@@ -284,50 +809,148 @@ impl Ai {
     }
 }
 
+// Search value of a grid where the Slider has no legal move, from the
+// Slider's point of view. Dying later is better, so the turn is added. A
+// grid holding the target tile is worth as much the other way, less the
+// turn since winning sooner is better.
+const DEATH_VALUE: i32 = 1_000_000_000;
+
+/// One root move of `Ai::analyze`. Values are from the point of view of the
+/// player to move at the root.
+#[derive(Debug, Clone)]
+pub struct RootMove {
+    pub m: Move,
+    pub value: i32,
+    // the search found a forced death for the Slider within the horizon
+    pub proven_death: bool,
+    // or a forced win, see GameRules::target
+    pub proven_win: bool,
+    // starts with `m`
+    pub pv: Vec<Move>,
+}
+
+fn moves_for<'a>(key: &NodeKey, r: &'a SearchRules) -> &'a [Move] {
+    if key.turns % 2 == 0 {
+        &r.placer_moves
+    } else {
+        &r.slider_moves[key.phase as usize]
+    }
+}
+
+// Turns count the tile sum in units of half the spawns' common divisor, less
+// one on the Slider's turn, so they go up by one per ply when only the
+// smallest spawn is placed (2s under the standard rules) one at a time, stay
+// odd on the Slider's turn and can be recovered from the grid and side to
+// move. Between the drops of a Placer's turn they go up by two.
+fn key_from_state(s: &State, r: &SearchRules) -> NodeKey {
+    let board = s.grid();
+    let mut grid = Grid::new(board.rows(), board.cols());
+    let mut turns = 0;
+    for (i, j) in board.coords() {
+        let val = board[i][j];
+        grid[i][j] = r.code(val).expect("Tile not in the rules");
+        turns += r.weight(val);
+    }
+    if s.next_to_move() == Role::Slider {
+        turns -= 1;
+    }
+    NodeKey {
+        turns,
+        grid,
+        phase: s.phase() as u8,
+        placed: s.placed() as u8,
+    }
+}
+
+fn state_from_key(key: &NodeKey, r: &SearchRules) -> State {
+    let mut grid = Board::new(key.grid.rows(), key.grid.cols());
+    for (i, j) in grid.coords() {
+        grid[i][j] = r.value(key.grid[i][j]);
+    }
+    let next_to_move = if key.turns % 2 == 0 {
+        Role::Placer
+    } else {
+        Role::Slider
+    };
+    State::new_with(grid, next_to_move, 0, r.rules.clone())
+        .with_phase(key.phase as usize)
+        .with_placed(key.placed as usize)
+}
+
 // TODO: this should update turns
 // TODO: replace with apply_all_moves
-fn apply_move(key: &NodeKey, m: Move) -> Option<NodeKey> {
-    let NodeKey { turns, grid } = key;
+fn apply_move(key: &NodeKey, m: Move, r: &SearchRules) -> Option<NodeKey> {
+    let NodeKey {
+        turns,
+        grid,
+        phase,
+        placed,
+    } = *key;
     let mut turn_increment = 1;
+    let mut next_phase = phase;
+    let mut next_placed = 0;
     match m {
-        Move::Slide(d) => match d {
-            Direction::Up => slide_up(grid),
-            Direction::Down => slide_down(grid),
-            Direction::Left => slide_left(grid),
-            Direction::Right => slide_right(grid),
-        },
+        Move::Slide(d) => {
+            if !r.slider_moves[phase as usize].contains(&m) {
+                return None;
+            }
+            next_phase = ((phase as usize + 1) % r.slider_moves.len()) as u8;
+            slide(&grid, d, r)
+        }
         Move::Place { x, y, val } => {
-            turn_increment = val - 1; // 1 or 3
-            place(grid, x, y, (val / 2) as u8)
+            turn_increment = r.weight(val) - 1;
+            let code = r.code(val).filter(|&c| c != 0)?;
+            if code == r.wall
+                && grid.cells().iter().filter(|&&c| c == r.wall).count() >= r.max_walls
+            {
+                return None;
+            }
+            let grid = place(&grid, x, y, code)?;
+            // see State::placed
+            if (placed as usize) + 1 < r.drops && grid.cells().contains(&0) {
+                turn_increment += 1;
+                next_placed = placed + 1;
+            }
+            Some(grid)
         }
     }
     .map(|grid| NodeKey {
-        turns: *turns + turn_increment,
+        turns: turns + turn_increment,
         grid,
+        phase: next_phase,
+        placed: next_placed,
     })
 }
 
-fn new_node(key: &NodeKey) -> NodeData {
-    let moves: &[Move] = if key.turns % 2 == 0 {
-        &PLACER_MOVES
-    } else {
-        if dead_grid(&key.grid) {
-            //println!("Dead grid at {} turns", key.turns);
-            return NodeData {
-                search_depth: i32::MAX, // exact value known
-                upper_bound: -1000_000_000 + key.turns,
-                lower_bound: -1000_000_000 + key.turns,
-                children: vec![],
-                best_child: None,
-            };
-        }
-        &SLIDER_MOVES
-    };
+fn new_node(key: &NodeKey, r: &SearchRules) -> NodeData {
+    // nobody moves once the target is made, which only a slide does
+    if r.won(&key.grid) {
+        let value = -(DEATH_VALUE - key.turns);
+        return NodeData {
+            search_depth: i32::MAX,
+            upper_bound: value,
+            lower_bound: value,
+            children: Vec::new(),
+            best_child: None,
+        };
+    }
+    let moves = moves_for(key, r);
     // TODO: lazy child init (None, Some(Vec<NodeKey>))
     let children: Vec<NodeKey> = moves
-        .into_iter()
-        .filter_map(|&m| apply_move(&key, m))
+        .iter()
+        .filter_map(|&m| apply_move(key, m, r))
         .collect();
+    // the Slider is dead when none of the slides it may make moves a tile
+    if key.turns % 2 == 1 && children.is_empty() {
+        //println!("Dead grid at {} turns", key.turns);
+        return NodeData {
+            search_depth: i32::MAX, // exact value known
+            upper_bound: -DEATH_VALUE + key.turns,
+            lower_bound: -DEATH_VALUE + key.turns,
+            children,
+            best_child: None,
+        };
+    }
 
     NodeData {
         search_depth: -1, // no heuristic calculated yet
@@ -338,48 +961,216 @@ fn new_node(key: &NodeKey) -> NodeData {
     }
 }
 
-fn dead_grid(g: &Grid) -> bool {
-    for i in 0..4 {
-        for j in 0..4 {
-            if g[i][j] == 0 {
-                return false;
-            }
+impl Ai {
+    // An Ai for games under rules, on an empty board. The depth is clamped
+    // to 1..=MAX_SEARCH_DEPTH, so there's always a move to pick.
+    pub fn with_rules(search_depth: i32, rules: Arc<dyn GameRules>) -> Ai {
+        utils::set_panic_hook();
+        let search_rules = SearchRules::new(rules.clone());
+        let root_key = key_from_state(&State::start(rules), &search_rules);
+        let mut sym_map = Vec::new();
+        let mut node_map = Vec::new();
+        for _ in 0..TURNS_MOD {
+            sym_map.push(HashMap::new());
+            node_map.push(HashMap::new());
         }
-        for j in 0..3 {
-            if g[i][j] == g[i][j + 1] {
-                return false;
-            }
-            if g[j][i] == g[j + 1][i] {
-                return false;
+
+        Ai {
+            sym_map,
+            node_map,
+            rules: search_rules,
+            root_key,
+            search_depth: search_depth.clamp(1, MAX_SEARCH_DEPTH),
+            difficulty: Difficulty::Adversarial,
+            time_limit: None,
+            last_info: None,
+            rng: StdRng::from_entropy(),
+            spawns: 0,
+            spawn_weights: Vec::new(),
+            deadline: None,
+            nodes: 0,
+            aborted: false,
+        }
+    }
+
+    // Node values depend on how the search expects the Placer to play, so
+    // the tables are cleared when that changes
+    pub fn set_difficulty(&mut self, difficulty: Difficulty) {
+        let model = self.placer_model();
+        self.difficulty = difficulty;
+        if self.placer_model() != model {
+            self.clear();
+        }
+    }
+
+    // Tiles placed by the random placements of the non-adversarial
+    // difficulties, with their relative weights. Panics unless every value
+    // is positive and the weights are non-negative with a positive sum.
+    pub fn set_spawns(&mut self, spawns: &[(i32, f64)]) {
+        assert!(
+            spawns.iter().all(|&(val, w)| val > 0 && w >= 0.0)
+                && spawns.iter().any(|&(_, w)| w > 0.0),
+            "Invalid spawns"
+        );
+        let model = self.placer_model();
+        self.spawn_weights = spawns.to_vec();
+        if self.placer_model() != model {
+            self.clear();
+        }
+    }
+
+    // The chance the search gives the Placer of playing adversarially
+    fn adversary(&self) -> f64 {
+        match self.difficulty {
+            Difficulty::Mixed(p) => p,
+            _ => 1.0,
+        }
+    }
+
+    // what node values depend on besides the rules, also kept in caches
+    fn placer_model(&self) -> String {
+        let p = self.adversary();
+        if p == 1.0 {
+            return "adversarial".to_string();
+        }
+        if self.spawn_weights.is_empty() {
+            return format!("mixed:{p}");
+        }
+        let spawns: Vec<String> = self
+            .spawn_weights
+            .iter()
+            .map(|(val, w)| format!("{val}:{w}"))
+            .collect();
+        format!("mixed:{p} spawns {}", spawns.join(","))
+    }
+
+    // the weight random placements give to placing val
+    fn spawn_weight(&self, val: i32) -> f64 {
+        match self.spawn_weights.as_slice() {
+            [] if val == self.rules.rules.spawns()[0] => 1.0,
+            [] => 0.0,
+            spawns => spawns
+                .iter()
+                .filter(|&&(v, _)| v == val)
+                .map(|&(_, w)| w)
+                .sum(),
+        }
+    }
+
+    // Whether value proves the game's result, a death or a win. A Mixed
+    // Placer's values average them with other outcomes, so only an
+    // adversarial one's do.
+    fn proven(&self, value: i32) -> bool {
+        self.adversary() == 1.0 && value.abs() > DEATH_VALUE / 2
+    }
+
+    // Search by time instead of the fixed search depth (native only, there
+    // is no clock on wasm32-unknown-unknown)
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.time_limit = limit;
+    }
+
+    // seeds the random choices made by the non-adversarial difficulties
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // Iterative deepening from the root. The next iteration takes at least
+    // as long as all previous ones together, so stop once half the time is
+    // used up, or when the result is a proven death or win. An iteration
+    // that runs out of time anyway is abandoned.
+    // Returns the depth completed and its root value.
+    fn timed_search(&mut self, limit: Duration) -> (i32, i32) {
+        let start = Instant::now();
+        let (mut depth, mut v) = (
+            1,
+            self.deepen(self.root_key, self.root_key.turns + 1, None)
+                .unwrap(),
+        );
+        while !self.proven(v) && depth < MAX_SEARCH_DEPTH && start.elapsed() * 2 < limit {
+            let max_depth = self.root_key.turns + depth + 1;
+            match self.deepen(self.root_key, max_depth, Some(start + limit)) {
+                Some(value) => (depth, v) = (depth + 1, value),
+                None => break,
             }
         }
+        (depth, v)
+    }
+
+    fn random_move(&mut self) -> Move {
+        let mut random = Random::new(self.rng.gen());
+        if !self.spawn_weights.is_empty() {
+            random = random.with_spawns(&self.spawn_weights);
+        }
+        random.pick_move(&state_from_key(&self.root_key, &self.rules))
+    }
+
+    fn softmax_move(&mut self, temperature: f64) -> Move {
+        let root_moves = self.analyze_key(self.root_key, self.search_depth);
+        let best = root_moves[0].value as f64;
+        let weights = root_moves
+            .iter()
+            .map(|rm| ((rm.value as f64 - best) / temperature).exp());
+        let dist = WeightedIndex::new(weights).unwrap();
+        root_moves[dist.sample(&mut self.rng)].m
     }
-    true
 }
 
 impl Player for Ai {
     fn pick_move(&mut self, _s: &State) -> Move {
+        self.last_info = None;
+        if self.root_key.turns % 2 == 0 {
+            self.spawns += 1;
+            match self.difficulty {
+                Difficulty::Adversarial => {}
+                Difficulty::Softmax(t) => return self.softmax_move(t),
+                Difficulty::Epsilon(e) => {
+                    if self.rng.gen_bool(e) {
+                        return self.random_move();
+                    }
+                }
+                Difficulty::EveryKth(k) => {
+                    if !self.spawns.is_multiple_of(k) {
+                        return self.random_move();
+                    }
+                }
+                Difficulty::Mixed(p) => {
+                    if !self.rng.gen_bool(p) {
+                        return self.random_move();
+                    }
+                }
+            }
+        }
+
         // TODO: assert state matches self.root_key.grid
-        let max_depth = self.root_key.turns + self.search_depth;
-        let v = self.negamax(self.root_key, max_depth, -i32::MAX, i32::MAX);
-        println!(
-            "negamax root value = {}, turns = {}",
-            v, self.root_key.turns
-        );
+        let (depth, value) = match self.time_limit {
+            Some(limit) => self.timed_search(limit),
+            None => {
+                let max_depth = self.root_key.turns + self.search_depth;
+                let v = self.negamax(self.root_key, max_depth, -i32::MAX, i32::MAX);
+                (self.search_depth, v)
+            }
+        };
+        let pv = self.principal_variation(self.root_key, self.root_key.turns + depth);
+        self.last_info = Some(EngineInfo { depth, value, pv });
         self.best_root_move()
     }
 
+    fn engine_info(&self) -> Option<EngineInfo> {
+        self.last_info.clone()
+    }
+
     fn update_move(&mut self, m: &Move, _s: &State) {
         log!("updating move");
         let old_turns = (self.root_key.turns % TURNS_MOD) as usize;
-        self.root_key = apply_move(&self.root_key, *m).unwrap();
+        self.root_key = apply_move(&self.root_key, *m, &self.rules).unwrap();
         self.sym_map[old_turns].clear();
         self.node_map[old_turns].clear();
         //println!("{:?}", self.root_key);
     }
 }
 
-fn heuristic(grid: &Grid) -> i32 {
+fn heuristic(grid: &Grid, r: &SearchRules) -> i32 {
     let mut score: i32 = 0;
     let mut penalty: i32 = 0;
 
@@ -391,52 +1182,65 @@ fn heuristic(grid: &Grid) -> i32 {
     const H_EQ: i32 = 2;
     const V_EQ: i32 = 2;
 
-    let mut sq = [[0; 4]; 4];
-    for i in 0..4 {
-        for j in 0..4 {
-            sq[i][j] = (grid[i][j] * grid[i][j]) as i32;
-        }
-    }
+    let (rows, cols) = (grid.rows(), grid.cols());
+    let sq = |i: usize, j: usize| (grid[i][j] as i32) * (grid[i][j] as i32);
+    // neighbours that merge, whichever slides into which
+    let merge = |a: u8, b: u8| r.merge(a, b) != 0 || r.merge(b, a) != 0;
+    // tiles on either side of a wall don't affect each other
+    let walls = r.max_walls > 0;
+    let apart = |a: u8, b: u8| walls && (a == r.wall || b == r.wall);
     // horizontal differences
-    for i in 0..4 {
-        for j in 0..3 {
-            let d = sq[i][j + 1] - sq[i][j];
+    for i in 0..rows {
+        for j in 0..cols - 1 {
+            if apart(grid[i][j], grid[i][j + 1]) {
+                continue;
+            }
+            let d = sq(i, j + 1) - sq(i, j);
             penalty += (2 * H_DIFF + H_REV) * d.abs() + H_REV * d;
-            if d == 0 {
-                score += H_EQ * sq[i][j];
+            if merge(grid[i][j], grid[i][j + 1]) {
+                score += H_EQ * sq(i, j);
             }
         }
     }
     // vertical differences
-    for i in 0..3 {
-        for j in 0..4 {
-            let d = sq[i + 1][j] - sq[i][j];
+    for i in 0..rows - 1 {
+        for j in 0..cols {
+            if apart(grid[i][j], grid[i + 1][j]) {
+                continue;
+            }
+            let d = sq(i + 1, j) - sq(i, j);
             penalty += (2 * V_DIFF + V_REV) * d.abs() + V_REV * d;
-            if d == 0 {
-                score += V_EQ * sq[i][j];
+            if merge(grid[i][j], grid[i + 1][j]) {
+                score += V_EQ * sq(i, j);
             }
         }
     }
     score - penalty
 }
 
-// TODO: optimize with bit operations when Grid = u64
-fn symmetries(grid: &Grid) -> [Grid; 8] {
-    let mut ret: [Grid; 8] = [[[0u8; 4]; 4]; 8];
-    for i in 0..4 {
-        for j in 0..4 {
+// The grids equivalent to grid: all 8 rotations and reflections of a
+// square board, or the 4 that keep a rectangular one's shape
+fn symmetries(grid: &Grid) -> Vec<Grid> {
+    let (rows, cols) = (grid.rows(), grid.cols());
+    let count = if rows == cols { 8 } else { 4 };
+    let mut ret = vec![Grid::new(rows, cols); count];
+    let (r, c) = (rows - 1, cols - 1);
+    for i in 0..rows {
+        for j in 0..cols {
             let num = grid[i][j];
             ret[0][i][j] = num;
-            ret[1][3 - i][j] = num;
-            ret[2][i][3 - j] = num;
-            ret[3][3 - i][3 - j] = num;
-            ret[4][j][i] = num;
-            ret[5][3 - j][i] = num;
-            ret[6][j][3 - i] = num;
-            ret[7][3 - j][3 - i] = num;
+            ret[1][r - i][j] = num;
+            ret[2][i][c - j] = num;
+            ret[3][r - i][c - j] = num;
+            if rows == cols {
+                ret[4][j][i] = num;
+                ret[5][c - j][i] = num;
+                ret[6][j][r - i] = num;
+                ret[7][c - j][r - i] = num;
+            }
         }
     }
-    return ret;
+    ret
 }
 
 #[wasm_bindgen]
@@ -448,81 +1252,90 @@ pub struct WasmPlace {
 
 #[wasm_bindgen]
 impl WasmPlace {
-    pub fn x(&self) -> usize { self.x }
-    pub fn y(&self) -> usize { self.y }
-    pub fn val(&self) -> i32 { self.val }
-
+    pub fn x(&self) -> usize {
+        self.x
+    }
+    pub fn y(&self) -> usize {
+        self.y
+    }
+    pub fn val(&self) -> i32 {
+        self.val
+    }
 }
 
 #[wasm_bindgen]
 impl Ai {
     pub fn new(search_depth: i32) -> Ai {
-        utils::set_panic_hook();
-        let root_key = NodeKey {
-            turns: 0,
-            grid: [[0u8; 4]; 4],
-        };
-        let mut sym_map = Vec::new();
-        let mut node_map = Vec::new();
-        for _ in 0..TURNS_MOD {
-            sym_map.push(HashMap::new());
-            node_map.push(HashMap::new());
-        }
+        Ai::with_size(search_depth, state::ROWS, state::COLS)
+    }
 
-        Ai {
-            sym_map,
-            node_map,
-            root_key,
-            search_depth,
-        }
+    // An Ai for standard games on an empty rows x cols board
+    pub fn with_size(search_depth: i32, rows: usize, cols: usize) -> Ai {
+        Ai::with_rules(search_depth, Arc::new(Standard::new(rows, cols)))
     }
 
+    // See Difficulty::from_str for the accepted strings. Others throw in JS.
+    pub fn with_difficulty(search_depth: i32, difficulty: &str) -> Result<Ai, JsValue> {
+        let difficulty = difficulty
+            .parse()
+            .map_err(|_| JsValue::from_str(&format!("invalid difficulty \"{difficulty}\"")))?;
+        let mut ai = Ai::new(search_depth);
+        ai.difficulty = difficulty;
+        Ok(ai)
+    }
 
     pub fn update_slider_move(&mut self, direction: i32) {
-        let m = Move::Slide(match direction {
-            0 => Direction::Up,
-            1 => Direction::Right,
-            2 => Direction::Down,
-            3 => Direction::Left,
-            _ => panic!("Invalid Direction"),
-        });
-        self.update_move(&m, &INITIAL_STATE);
+        let m = Move::Slide(Direction::from_index(direction).expect("Invalid Direction"));
+        self.update_move(&m, &State::default());
     }
 
     pub fn get_placer_move(&mut self) -> WasmPlace {
-        let m = self.pick_move(&INITIAL_STATE);
-        self.update_move(&m, &INITIAL_STATE);
+        let m = self.pick_move(&State::default());
+        self.update_move(&m, &State::default());
         match m {
             Move::Place { x, y, val } => WasmPlace { x, y, val },
             _ => panic!("Invalid move returned from pick_move"),
         }
     }
 
-    pub fn init_from_grid(&mut self, grid1d: &[u8]) {
+    // grid1d holds log2 of each tile, row-major, with the Slider to move.
+    // The board keeps the size the Ai was made with. A grid of another size
+    // or with tiles the rules don't have throws in JS. Grids no game could
+    // reach are taken, as the web game starts from two random tiles.
+    pub fn init_from_grid(&mut self, grid1d: &[u8]) -> Result<(), JsValue> {
         log!("init from {grid1d:?}");
-        
-        let mut grid = [[0u8; 4]; 4];
-        let mut turns = -1;
-        for i in 0..16 {
-            grid[i/4][i%4] = grid1d[i];
-            turns += 2 * grid1d[i] as i32;
-        }
-        self.root_key = NodeKey {
-            turns,
-            grid,
-        };
 
-        for i in 0..TURNS_MOD {
-            self.sym_map[i as usize].clear();
-            self.node_map[i as usize].clear();
+        let (rows, cols) = (self.root_key.grid.rows(), self.root_key.grid.cols());
+        if grid1d.len() != rows * cols {
+            return Err(JsValue::from_str(&format!(
+                "expected {} cells, got {}",
+                rows * cols,
+                grid1d.len()
+            )));
+        }
+        let mut grid = Board::new(rows, cols);
+        for (i, &exp) in grid1d.iter().enumerate() {
+            if u32::from(exp) > rules::MAX_TILE.ilog2() {
+                return Err(JsValue::from_str(&format!("tile 2^{exp} is too large")));
+            }
+            if exp != 0 {
+                grid[i / cols][i % cols] = 1 << exp;
+            }
+        }
+        let s = State::new_with(grid, Role::Slider, 0, self.rules.rules.clone());
+        if let Err(InvalidState::BadTile { x, y, val }) = s.check() {
+            return Err(JsValue::from_str(&format!("invalid tile {val} at {x},{y}")));
         }
+        self.root_key = key_from_state(&s, &self.rules);
+        self.clear();
+        Ok(())
     }
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::random::CLASSIC_SPAWNS;
 
     /*
     #[test]
@@ -533,12 +1346,12 @@ mod tests {
         assert_eq!(hash1, hash2);
     }
     */
-    use crate::state::INITIAL_STATE;
+    use crate::state;
 
     #[test]
     #[ignore]
     fn predict_death() {
-        let s = INITIAL_STATE;
+        let s = State::default();
         let mut ai = Ai::new(13);
         let m = ai.pick_move(&s);
         //let m = Move::Slide(Direction::Left);
@@ -549,29 +1362,304 @@ mod tests {
         println!("chosen move: {:?}", m);
         ai.print_node(NodeKey {
             turns: 5025,
-            grid: [[5, 9, 10, 11], [4, 6, 8, 10], [1, 3, 4, 2], [4, 1, 1, 0]],
+            grid: [[5, 9, 10, 11], [4, 6, 8, 10], [1, 3, 4, 2], [4, 1, 1, 0]].into(),
+            phase: 0,
+            placed: 0,
         });
         ai.print_node(NodeKey {
             turns: 5026,
-            grid: [[11, 10, 9, 5], [10, 8, 6, 4], [2, 4, 3, 1], [0, 0, 2, 4]],
+            grid: [[11, 10, 9, 5], [10, 8, 6, 4], [2, 4, 3, 1], [0, 0, 2, 4]].into(),
+            phase: 0,
+            placed: 0,
         });
         //ai.print_node(NodeKey { turns: 5027, grid: [[11, 10, 9, 5], [10, 8, 6, 4], [2, 4, 3, 1], [0, 1, 2, 4]] });
         ai.print_node(NodeKey {
             turns: 5027,
-            grid: [[11, 10, 9, 5], [10, 8, 6, 4], [2, 4, 3, 1], [1, 0, 2, 4]],
+            grid: [[11, 10, 9, 5], [10, 8, 6, 4], [2, 4, 3, 1], [1, 0, 2, 4]].into(),
+            phase: 0,
+            placed: 0,
         });
         ai.update_move(&m, &s);
         let m = ai.pick_move(&s);
         println!("chosen move: {:?}", m);
     }
 
+    #[test]
+    fn analyze_root_moves() {
+        let s = State::default();
+        let mut ai = Ai::new(4);
+        let root_moves = ai.analyze(&s, 4).unwrap();
+        assert_eq!(root_moves.len(), 32);
+        for pair in root_moves.windows(2) {
+            assert!(pair[0].value >= pair[1].value);
+        }
+        for rm in &root_moves {
+            assert!(!rm.proven_death);
+            assert!(matches!(rm.pv[0], Move::Place { .. }));
+        }
+        // depths are clamped, and only positions the rules allow are searched
+        assert_eq!(ai.analyze(&s, 0).unwrap().len(), 32);
+        let s = State::new([[3, 0, 0, 0], [0; 4], [0; 4], [0; 4]], Role::Slider, 0);
+        assert_eq!(
+            ai.analyze(&s, 2).unwrap_err(),
+            InvalidState::BadTile { x: 0, y: 0, val: 3 }
+        );
+    }
+
+    #[test]
+    fn analyze_proven_death() {
+        // a 2 in the last hole leaves no merges, a 4 doesn't
+        let grid = [[2, 4, 2, 4], [4, 2, 4, 2], [2, 4, 2, 4], [8, 16, 8, 0]];
+        let s = State::new(grid, Role::Placer, 0);
+        let mut ai = Ai::new(2);
+        let root_moves = ai.analyze(&s, 2).unwrap();
+        assert_eq!(root_moves.len(), 2);
+        assert!(matches!(
+            root_moves[0].m,
+            Move::Place { x: 3, y: 3, val: 2 }
+        ));
+        assert!(root_moves[0].proven_death);
+        assert!(root_moves[0].value > root_moves[1].value);
+    }
+
+    #[test]
+    fn best_move_either_side() {
+        let mut ai = Ai::new(4);
+        let grid = [[2, 4, 2, 4], [4, 2, 4, 2], [2, 0, 2, 4], [8, 16, 8, 0]];
+        let s = State::new(grid, Role::Placer, 0);
+        assert_eq!(ai.best_move(&s), Err(InvalidState::Unreachable));
+
+        let grid = [[2, 4, 2, 4], [4, 2, 4, 2], [2, 4, 8, 0], [8, 16, 0, 0]];
+        let s = State::new(grid, Role::Placer, 0);
+        let m = ai.best_move(&s).unwrap().unwrap();
+        assert!(matches!(m, Move::Place { .. }));
+
+        let grid = [[2, 4, 2, 4], [4, 2, 4, 2], [2, 4, 8, 2], [8, 16, 0, 0]];
+        let s = State::new(grid, Role::Slider, 0);
+        let m = ai.best_move(&s).unwrap().unwrap();
+        assert!(state::next_state(&s, m).is_ok());
+
+        let grid = [[2, 4, 2, 4], [4, 2, 4, 2], [2, 4, 2, 4], [8, 16, 8, 2]];
+        let s = State::new(grid, Role::Slider, 0);
+        assert_eq!(ai.best_move(&s).unwrap(), None);
+    }
+
+    #[test]
+    fn shallow_depths() {
+        for depth in [0, -3] {
+            let mut ai = Ai::new(depth);
+            assert!(matches!(
+                ai.pick_move(&State::default()),
+                Move::Place { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn init_from_grid_turns() {
+        let mut ai = Ai::new(4);
+        let mut grid1d = [0u8; 16];
+        grid1d[0] = 3;
+        grid1d[5] = 1;
+        ai.init_from_grid(&grid1d).unwrap();
+        assert_eq!(ai.root_key.turns, 8 + 2 - 1);
+    }
+
+    #[test]
+    fn parse_difficulty() {
+        assert_eq!("adversarial".parse(), Ok(Difficulty::Adversarial));
+        assert_eq!("softmax:50".parse(), Ok(Difficulty::Softmax(50.0)));
+        assert_eq!("epsilon:0.25".parse(), Ok(Difficulty::Epsilon(0.25)));
+        assert_eq!("every:3".parse(), Ok(Difficulty::EveryKth(3)));
+        assert_eq!("mixed:0.75".parse(), Ok(Difficulty::Mixed(0.75)));
+        assert_eq!("mixed:-1".parse::<Difficulty>(), Err(InvalidDifficulty));
+        assert_eq!("epsilon:2".parse::<Difficulty>(), Err(InvalidDifficulty));
+        assert_eq!("every:0".parse::<Difficulty>(), Err(InvalidDifficulty));
+        assert_eq!("softmax".parse::<Difficulty>(), Err(InvalidDifficulty));
+        assert_eq!("easy".parse::<Difficulty>(), Err(InvalidDifficulty));
+        let d = Difficulty::Softmax(0.5);
+        assert_eq!(d.to_string().parse(), Ok(d));
+    }
+
+    #[test]
+    fn softmax_placer() {
+        // with a tiny temperature only the killing placement has any weight
+        let grid = [[2, 4, 2, 4], [4, 2, 4, 2], [2, 4, 2, 4], [8, 16, 8, 0]];
+        let mut ai = Ai::with_difficulty(2, "softmax:0.001").unwrap();
+        ai.root_key = key_from_state(&State::new(grid, Role::Placer, 0), &ai.rules);
+        for _ in 0..10 {
+            let m = ai.pick_move(&State::default());
+            assert_eq!(m, Move::Place { x: 3, y: 3, val: 2 });
+        }
+    }
+
+    #[test]
+    fn random_placer_moves_are_legal() {
+        for difficulty in ["epsilon:1", "every:2", "mixed:0.5"] {
+            let mut ai = Ai::with_difficulty(2, difficulty).unwrap();
+            let mut s = State::default();
+            for _ in 0..10 {
+                let m = ai.pick_move(&s);
+                s = state::next_state(&s, m).unwrap();
+                ai.update_move(&m, &s);
+            }
+        }
+    }
+
+    #[test]
+    fn spawn_sets() {
+        // placing the smallest spawn is one turn, whatever it is
+        let rules: Arc<dyn GameRules> = Arc::new(Standard::with_spawns(4, 4, &[4, 8]));
+        let search = SearchRules::new(rules.clone());
+        let mut s = State::start(rules);
+        let mut random = Random::new(1);
+        for ply in 0..20 {
+            let key = key_from_state(&s, &search);
+            assert_eq!(key.turns, ply);
+            let m = random.pick_move(&s);
+            s = state::next_state(&s, m).unwrap();
+            assert_eq!(
+                apply_move(&key, m, &search),
+                Some(key_from_state(&s, &search))
+            );
+        }
+
+        // only a 2 kills, and only if 2s are placed
+        let grid = [[4, 8, 4, 8], [8, 4, 8, 4], [4, 8, 32, 8], [8, 16, 4, 0]];
+        for (spawns, kill) in [(&[2, 4, 8][..], true), (&[4, 8], false)] {
+            let rules = Arc::new(Standard::with_spawns(4, 4, spawns));
+            let s = State::new_with(grid, Role::Placer, 0, rules.clone());
+            let mut ai = Ai::with_rules(3, rules);
+            let m = ai.best_move(&s).unwrap().unwrap();
+            assert!(state::next_state(&s, m).is_ok());
+            assert_eq!(m == Move::Place { x: 3, y: 3, val: 2 }, kill);
+        }
+    }
+
+    #[test]
+    fn mixed_placer() {
+        // one ply down every child is a leaf, whatever the Placer is like
+        let s = State::from_notation("2,4,0,0/8,0,0,0/2,0,0,0/0,0,0,0 p").unwrap();
+        let values: Vec<i32> = Ai::new(1)
+            .analyze(&s, 1)
+            .unwrap()
+            .iter()
+            .map(|rm| rm.value)
+            .collect();
+        let best = values[0] as f64;
+        let twos: Vec<f64> = Ai::new(1)
+            .analyze(&s, 1)
+            .unwrap()
+            .iter()
+            .filter(|rm| matches!(rm.m, Move::Place { val: 2, .. }))
+            .map(|rm| rm.value as f64)
+            .collect();
+        let mean = twos.iter().sum::<f64>() / twos.len() as f64;
+        for p in [0.0, 0.3, 1.0] {
+            let mut ai = Ai::new(1);
+            ai.set_difficulty(Difficulty::Mixed(p));
+            let info = ai.search(&s, 1, None, |_| {}).unwrap().unwrap();
+            assert_eq!(info.value, (p * best + (1.0 - p) * mean).round() as i32);
+        }
+
+        // an adversarial Mixed search is the plain one
+        let s = State::from_notation("2,4,0,0/0,8,0,0/0,0,2,0/0,0,0,0 s").unwrap();
+        let mut ai = Ai::new(5);
+        ai.set_difficulty(Difficulty::Mixed(1.0));
+        let mixed: Vec<(Move, i32)> = ai
+            .analyze(&s, 5)
+            .unwrap()
+            .iter()
+            .map(|rm| (rm.m, rm.value))
+            .collect();
+        let plain: Vec<(Move, i32)> = Ai::new(5)
+            .analyze(&s, 5)
+            .unwrap()
+            .iter()
+            .map(|rm| (rm.m, rm.value))
+            .collect();
+        assert_eq!(mixed, plain);
+
+        // the random placements follow the spawns
+        let mut ai = Ai::new(4);
+        ai.set_difficulty(Difficulty::Mixed(0.0));
+        let twos = ai.search(&s, 4, None, |_| {}).unwrap().unwrap().value;
+        ai.set_spawns(&CLASSIC_SPAWNS);
+        assert_ne!(ai.search(&s, 4, None, |_| {}).unwrap().unwrap().value, twos);
+    }
+
+    #[test]
+    fn target_search() {
+        let name = "standard+target:8";
+        // merging the 4s wins, whatever the Placer does first
+        let s = State::from_notation_with("4,4,0/2,0,0/0,0,0 s", name).unwrap();
+        let root_moves = Ai::new(3).analyze(&s, 3).unwrap();
+        let wins: Vec<Move> = root_moves
+            .iter()
+            .filter(|rm| rm.proven_win)
+            .map(|rm| rm.m)
+            .collect();
+        assert_eq!(wins.len(), 2);
+        assert!(wins.contains(&Move::Slide(Direction::Left)));
+        assert!(root_moves.iter().all(|rm| !rm.proven_death));
+        // placing a 4 takes three turns, see key_from_state
+        let s = State::from_notation_with("4,4,0/0,0,0/0,0,0 p", name).unwrap();
+        let root_moves = Ai::new(5).analyze(&s, 5).unwrap();
+        assert!(root_moves
+            .iter()
+            .all(|rm| rm.proven_win && !rm.proven_death));
+        // and the search stops once it's proven
+        let mut ai = Ai::new(3);
+        let info = ai.search(&s, 20, None, |_| {}).unwrap().unwrap();
+        assert!(info.depth < 20 && info.value < -DEATH_VALUE / 2);
+    }
+
+    #[test]
+    fn timed_search() {
+        let mut ai = Ai::new(0);
+        ai.set_time_limit(Some(Duration::from_millis(20)));
+        let mut s = State::default();
+        for _ in 0..4 {
+            let m = ai.pick_move(&s);
+            s = state::next_state(&s, m).unwrap();
+            ai.update_move(&m, &s);
+        }
+
+        // a deep iteration is cut short rather than finished
+        let limit = Duration::from_millis(100);
+        let start = Instant::now();
+        let info = Ai::new(0)
+            .search(&State::default(), MAX_SEARCH_DEPTH, Some(limit), |_| {})
+            .unwrap()
+            .unwrap();
+        assert!(start.elapsed() < 3 * limit);
+        assert!(info.depth < MAX_SEARCH_DEPTH && !info.pv.is_empty());
+    }
+
+    #[test]
+    fn rectangular_symmetries() {
+        let grid = Grid::from([[1, 2, 0], [0, 0, 3]]);
+        let syms = symmetries(&grid);
+        assert_eq!(syms.len(), 4);
+        assert!(syms.contains(&Grid::from([[3, 0, 0], [0, 2, 1]])));
+        assert_eq!(symmetries(&Grid::new(3, 3)).len(), 8);
+
+        // the Placer picks a legal cell on a board that isn't 4x4
+        let mut ai = Ai::with_size(4, 2, 3);
+        let s = State::empty(2, 3);
+        let m = ai.pick_move(&s);
+        assert!(state::next_state(&s, m).is_ok());
+    }
+
     #[test]
     fn empty_children_bug() {
         let key = NodeKey {
             turns: 771,
-            grid: [[8, 7, 6, 5], [7, 6, 4, 3], [5, 4, 3, 2], [1, 3, 2, 1]],
+            grid: [[8, 7, 6, 5], [7, 6, 4, 3], [5, 4, 3, 2], [1, 3, 2, 1]].into(),
+            phase: 0,
+            placed: 0,
         };
-        let node = new_node(&key);
+        let node = new_node(&key, &SearchRules::new(Arc::new(Standard::default())));
         println!("{node:?}");
     }
 }
@@ -0,0 +1,395 @@
+// Saving and loading the search tree's node data, so that positions searched
+// in one session don't have to be searched again in the next.
+//
+// File layout (little endian):
+//   magic "A2048TT\0", version: u32, rows: u8, cols: u8,
+//   rules name length: u8, rules name, placer model length: u8, placer
+//   model, entry count: u64
+//   per entry: turns: i32, grid: rows x cols x u8, phase: u8, placed: u8,
+//              search_depth: i32, lower_bound: i32, upper_bound: i32,
+//              has_best_child: u8, [best child turns: i32, grid, phase,
+//              placed]
+// Grids are the canonical (max over the rules' symmetries) grids used as map
+// keys, with tiles and walls as the search's codes for them under the rules.
+// Children aren't stored since new_node recomputes them from the key.
+
+use super::{new_node, symmetries, Ai, Grid, NodeKey, SearchRules, TURNS_MOD};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"A2048TT\0";
+const VERSION: u32 = 6;
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    // the file was saved by an Ai playing on another board size
+    BoardSize { rows: usize, cols: usize },
+    // the file was saved by an Ai playing under other rules
+    Rules(String),
+    // the file was saved by an Ai expecting another Placer, see
+    // Difficulty::Mixed
+    Model(String),
+    // an entry that can't come from a search, e.g. a non-canonical grid or
+    // a best child that isn't a child
+    Corrupt,
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::Io(e) => write!(f, "i/o error: {e}"),
+            CacheError::BadMagic => write!(f, "not a search cache file"),
+            CacheError::UnsupportedVersion(v) => {
+                write!(f, "unsupported cache version {v} (expected {VERSION})")
+            }
+            CacheError::BoardSize { rows, cols } => {
+                write!(f, "cache is for a {rows}x{cols} board")
+            }
+            CacheError::Rules(name) => write!(f, "cache is for the {name} rules"),
+            CacheError::Model(model) => write!(f, "cache is for a {model} Placer"),
+            CacheError::Corrupt => write!(f, "invalid cache entry"),
+        }
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> CacheError {
+        CacheError::Io(e)
+    }
+}
+
+struct Entry {
+    key: NodeKey,
+    search_depth: i32,
+    lower_bound: i32,
+    upper_bound: i32,
+    best_child: Option<NodeKey>,
+}
+
+impl Ai {
+    /// Writes every searched node to `path`, returning the number of
+    /// entries written.
+    pub fn save_cache<P: AsRef<Path>>(&self, path: P) -> Result<usize, CacheError> {
+        let mut entries = Vec::new();
+        for (idx, nodes) in self.node_map.iter().enumerate() {
+            for (&(grid, phase, placed), node) in nodes {
+                // nodes that were only created as children carry no information
+                if node.search_depth < 0 {
+                    continue;
+                }
+                entries.push(Entry {
+                    key: NodeKey {
+                        turns: turns_of(&grid, idx, &self.rules),
+                        grid,
+                        phase,
+                        placed,
+                    },
+                    search_depth: node.search_depth,
+                    lower_bound: node.lower_bound,
+                    upper_bound: node.upper_bound,
+                    best_child: node.best_child,
+                });
+            }
+        }
+
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&[self.root_key.grid.rows, self.root_key.grid.cols])?;
+        let name = self.rules.rules.name();
+        w.write_all(&[name.len() as u8])?;
+        w.write_all(name.as_bytes())?;
+        let model = self.placer_model();
+        w.write_all(&[model.len() as u8])?;
+        w.write_all(model.as_bytes())?;
+        w.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for e in &entries {
+            write_key(&mut w, &e.key)?;
+            w.write_all(&e.search_depth.to_le_bytes())?;
+            w.write_all(&e.lower_bound.to_le_bytes())?;
+            w.write_all(&e.upper_bound.to_le_bytes())?;
+            match &e.best_child {
+                Some(child) => {
+                    w.write_all(&[1])?;
+                    write_key(&mut w, child)?;
+                }
+                None => w.write_all(&[0])?,
+            }
+        }
+        w.flush()?;
+        Ok(entries.len())
+    }
+
+    /// Loads entries saved by `save_cache`, returning the number of entries
+    /// kept, i.e. searched deeper than what the Ai already knew. The whole
+    /// file is validated before anything is inserted. Entries for turns
+    /// already behind the current root are skipped, so when resuming a
+    /// game, set up the position before loading.
+    pub fn load_cache<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, CacheError> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(CacheError::BadMagic);
+        }
+        let version = read_u32(&mut r)?;
+        if version != VERSION {
+            return Err(CacheError::UnsupportedVersion(version));
+        }
+        let mut size = [0u8; 2];
+        r.read_exact(&mut size)?;
+        let root = self.root_key.grid;
+        if size != [root.rows, root.cols] {
+            return Err(CacheError::BoardSize {
+                rows: size[0] as usize,
+                cols: size[1] as usize,
+            });
+        }
+        let name = read_string(&mut r)?;
+        if name != self.rules.rules.name() {
+            return Err(CacheError::Rules(name));
+        }
+        let model = read_string(&mut r)?;
+        if model != self.placer_model() {
+            return Err(CacheError::Model(model));
+        }
+        let count = read_u64(&mut r)?;
+
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let key = read_key(&mut r, root, &self.rules)?;
+            let search_depth = read_u32(&mut r)? as i32;
+            let lower_bound = read_u32(&mut r)? as i32;
+            let upper_bound = read_u32(&mut r)? as i32;
+            let mut flag = [0u8; 1];
+            r.read_exact(&mut flag)?;
+            let best_child = match flag[0] {
+                0 => None,
+                1 => Some(read_key(&mut r, root, &self.rules)?),
+                _ => return Err(CacheError::Corrupt),
+            };
+            let e = Entry {
+                key,
+                search_depth,
+                lower_bound,
+                upper_bound,
+                best_child,
+            };
+            if !valid_entry(&e, &self.rules) {
+                return Err(CacheError::Corrupt);
+            }
+            entries.push(e);
+        }
+
+        let mut loaded = 0;
+        for e in entries {
+            if e.key.turns < self.root_key.turns {
+                continue;
+            }
+            let (_, node) = self.key_to_node(e.key);
+            if node.search_depth < e.search_depth {
+                node.search_depth = e.search_depth;
+                node.lower_bound = e.lower_bound;
+                node.upper_bound = e.upper_bound;
+                node.best_child = e.best_child;
+                loaded += 1;
+            }
+        }
+        Ok(loaded)
+    }
+}
+
+// Nodes are stored by turns % TURNS_MOD, but the full turn count follows
+// from the tile sum, see key_from_state.
+fn turns_of(grid: &Grid, idx: usize, r: &SearchRules) -> i32 {
+    let sum = tile_sum(grid, r);
+    if idx.is_multiple_of(2) {
+        sum
+    } else {
+        sum - 1
+    }
+}
+
+// the tile sum in turns
+fn tile_sum(grid: &Grid, r: &SearchRules) -> i32 {
+    grid.cells().iter().map(|&v| r.weight(r.value(v))).sum()
+}
+
+fn valid_key(key: &NodeKey, r: &SearchRules) -> bool {
+    if key.grid.cells().iter().any(|&v| v > r.wall)
+        || key.phase as usize >= r.slider_moves.len()
+        || key.placed as usize >= r.drops
+        || (key.placed > 0 && key.turns % 2 == 1)
+    {
+        return false;
+    }
+    key.turns >= 0 && key.turns == turns_of(&key.grid, (key.turns % TURNS_MOD) as usize, r)
+}
+
+fn valid_entry(e: &Entry, r: &SearchRules) -> bool {
+    if !valid_key(&e.key, r) || e.lower_bound > e.upper_bound {
+        return false;
+    }
+    let grids = symmetries(&e.key.grid);
+    if r.symmetries.iter().map(|&t| grids[t]).max() != Some(e.key.grid) {
+        return false;
+    }
+    match &e.best_child {
+        Some(child) => new_node(&e.key, r).children.contains(child),
+        None => true,
+    }
+}
+
+fn write_key<W: Write>(w: &mut W, key: &NodeKey) -> io::Result<()> {
+    w.write_all(&key.turns.to_le_bytes())?;
+    w.write_all(key.grid.cells())?;
+    w.write_all(&[key.phase, key.placed])
+}
+
+// empty gives the board size
+fn read_key<R: Read>(r: &mut R, empty: Grid, rules: &SearchRules) -> Result<NodeKey, CacheError> {
+    let turns = read_u32(r)? as i32;
+    let mut grid = empty;
+    let len = grid.cells().len();
+    r.read_exact(&mut grid.cells[..len])?;
+    let mut turn = [0u8; 2];
+    r.read_exact(&mut turn)?;
+    let key = NodeKey {
+        turns,
+        grid,
+        phase: turn[0],
+        placed: turn[1],
+    };
+    if valid_key(&key, rules) {
+        Ok(key)
+    } else {
+        Err(CacheError::Corrupt)
+    }
+}
+
+// a u8 length, then that many bytes
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let mut len = [0u8; 1];
+    r.read_exact(&mut len)?;
+    let mut bytes = vec![0; len[0] as usize];
+    r.read_exact(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::Difficulty;
+    use crate::state::State;
+    use crate::Player;
+    use std::env;
+    use std::fs;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("a2048-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn roundtrip() {
+        let path = temp_path("roundtrip");
+        let mut ai = Ai::new(5);
+        let m = ai.pick_move(&State::default());
+        let saved = ai.save_cache(&path).unwrap();
+        assert!(saved > 0);
+
+        let mut warm = Ai::new(5);
+        assert_eq!(warm.load_cache(&path).unwrap(), saved);
+        let (_, root) = warm.key_to_node(warm.root_key);
+        assert_eq!(root.search_depth, 5);
+        assert!(root.best_child.is_some());
+        assert_eq!(warm.best_root_move(), m);
+        // nothing new the second time
+        assert_eq!(warm.load_cache(&path).unwrap(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let path = temp_path("bad");
+        fs::write(&path, b"not a cache file").unwrap();
+        assert!(matches!(
+            Ai::new(5).load_cache(&path),
+            Err(CacheError::BadMagic)
+        ));
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(0u64.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Ai::new(5).load_cache(&path),
+            Err(CacheError::UnsupportedVersion(1))
+        ));
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend([3u8, 3]);
+        bytes.extend(0u64.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Ai::new(5).load_cache(&path),
+            Err(CacheError::BoardSize { rows: 3, cols: 3 })
+        ));
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend([4u8, 4, 5]);
+        bytes.extend(b"other");
+        bytes.extend(0u64.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Ai::new(5).load_cache(&path),
+            Err(CacheError::Rules(name)) if name == "other"
+        ));
+
+        // saved by a search expecting a partly random Placer
+        let mut mixed = Ai::new(3);
+        mixed.set_difficulty(Difficulty::Mixed(0.5));
+        mixed.best_move(&State::default()).unwrap();
+        mixed.save_cache(&path).unwrap();
+        assert!(matches!(
+            Ai::new(3).load_cache(&path),
+            Err(CacheError::Model(model)) if model == "mixed:0.5"
+        ));
+
+        // one entry whose turn count doesn't match its tiles
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend([4u8, 4, 8]);
+        bytes.extend(b"standard");
+        bytes.push(11);
+        bytes.extend(b"adversarial");
+        bytes.extend(1u64.to_le_bytes());
+        bytes.extend(7i32.to_le_bytes());
+        bytes.extend([1u8; 16]);
+        bytes.extend([0u8, 0]);
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            Ai::new(5).load_cache(&path),
+            Err(CacheError::Corrupt)
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
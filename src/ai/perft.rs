@@ -0,0 +1,209 @@
+// Move generation counts, for checking that the slide and place rules in
+// state.rs and the search's own copies of them here in ai.rs agree. Every
+// position reached is expanded with both, and the first move they disagree
+// on is reported.
+//
+// Positions are told apart by grid, side to move, phase and tiles placed this
+// turn only, since the search doesn't keep the score.
+
+use super::{apply_move, key_from_state, moves_for, state_from_key, symmetries, Grid, SearchRules};
+use crate::state::{self, Board, Move, Role, State};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+type Position = (Board, Role, usize, usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerftCounts {
+    // move sequences of this length
+    pub paths: u64,
+    // distinct positions they end in
+    pub positions: usize,
+    // the same, counting positions that are symmetric to each other once
+    pub canonical: usize,
+}
+
+// A move the two implementations disagree on. None means the move was
+// illegal for that implementation.
+#[derive(Debug)]
+pub struct Divergence {
+    pub position: State,
+    pub m: Move,
+    pub rules: Option<State>,
+    pub search: Option<State>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |s: &Option<State>| match s {
+            Some(s) => s.to_notation(),
+            None => "illegal".to_string(),
+        };
+        write!(
+            f,
+            "{} after {}: state.rs gives {}, ai.rs gives {}",
+            self.position.to_notation(),
+            self.m,
+            show(&self.rules),
+            show(&self.search)
+        )
+    }
+}
+
+// Counts for every ply from 0 (just s) to depth
+pub fn perft(s: &State, depth: u32) -> Result<Vec<PerftCounts>, Box<Divergence>> {
+    let search_rules = SearchRules::new(s.rules().clone());
+    let mut level: HashMap<Position, (State, u64)> = HashMap::new();
+    level.insert(position(s), (s.clone(), 1));
+    let mut counts = vec![count(&level, &search_rules)];
+    for _ in 0..depth {
+        let mut next: HashMap<Position, (State, u64)> = HashMap::new();
+        for (s, paths) in level.values() {
+            for child in children(s, &search_rules)? {
+                let entry = next.entry(position(&child)).or_insert((child, 0));
+                entry.1 += paths;
+            }
+        }
+        level = next;
+        counts.push(count(&level, &search_rules));
+    }
+    Ok(counts)
+}
+
+fn position(s: &State) -> Position {
+    (s.grid().clone(), s.next_to_move(), s.phase(), s.placed())
+}
+
+fn children(s: &State, sr: &SearchRules) -> Result<Vec<State>, Box<Divergence>> {
+    if s.terminal() {
+        return Ok(Vec::new());
+    }
+    let moves = s.moves();
+    let key = key_from_state(s, sr);
+    // the search only knows whose turn it is from the tile sum
+    let search_moves = moves_for(&key, sr);
+    let mut children = Vec::new();
+    for &m in &moves {
+        let rules = state::next_state(s, m).ok();
+        let search = if search_moves.contains(&m) {
+            apply_move(&key, m, sr).map(|k| state_from_key(&k, sr))
+        } else {
+            None
+        };
+        let same = match (&rules, &search) {
+            (Some(r), Some(a)) => position(r) == position(a),
+            (None, None) => true,
+            _ => false,
+        };
+        if !same {
+            return Err(Box::new(Divergence {
+                position: s.clone(),
+                m,
+                rules,
+                search,
+            }));
+        }
+        children.extend(rules);
+    }
+    Ok(children)
+}
+
+fn count(level: &HashMap<Position, (State, u64)>, sr: &SearchRules) -> PerftCounts {
+    let canonical: HashSet<(i32, Grid, u8, u8)> = level
+        .values()
+        .map(|(s, _)| {
+            let key = key_from_state(s, sr);
+            let grids = symmetries(&key.grid);
+            let max_grid = sr.symmetries.iter().map(|&t| grids[t]).max().unwrap();
+            (key.turns, max_grid, key.phase, key.placed)
+        })
+        .collect();
+    PerftCounts {
+        paths: level.values().map(|&(_, paths)| paths).sum(),
+        positions: level.len(),
+        canonical: canonical.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules;
+
+    // (paths, positions, canonical) for every ply
+    fn table(s: &State, depth: u32) -> Vec<(u64, usize, usize)> {
+        perft(s, depth)
+            .unwrap()
+            .iter()
+            .map(|c| (c.paths, c.positions, c.canonical))
+            .collect()
+    }
+
+    #[test]
+    fn known_counts() {
+        assert_eq!(
+            table(&State::default(), 5),
+            [
+                (1, 1, 1),
+                (32, 32, 6),
+                (96, 24, 4),
+                (2880, 456, 69),
+                (10464, 136, 20),
+                (295296, 3142, 409),
+            ]
+        );
+
+        let s = State::from_notation("2,2,4,8/4,0,0,0/0,0,0,0/0,0,0,2 s").unwrap();
+        assert_eq!(
+            table(&s, 3),
+            [(1, 1, 1), (4, 4, 4), (84, 84, 84), (327, 108, 95)]
+        );
+
+        // a board with 4 symmetries instead of 8
+        assert_eq!(
+            table(&State::empty(2, 3), 4),
+            [
+                (1, 1, 1),
+                (12, 12, 4),
+                (28, 12, 4),
+                (280, 60, 20),
+                (872, 44, 14)
+            ]
+        );
+    }
+
+    #[test]
+    fn other_rules() {
+        // placements on the empty board: a value or wall on each free cell
+        for (name, placements) in [
+            ("fibonacci", 2 * 9),
+            ("threes", 3 * 9),
+            ("standard+blocked:1,1+walls:1", 3 * 8),
+            ("threes+walls:2", 4 * 9),
+            ("standard+slides:UDL", 2 * 9),
+            ("standard+drops:2", 2 * 9),
+            ("threes+walls:1+drops:3", 4 * 9),
+            ("fibonacci+walls:1+slides:DLR,UDL,ULR,UDR", 3 * 9),
+            ("standard+target:16", 2 * 9),
+        ] {
+            let s = State::start(rules::by_name(name, 3, 3).unwrap());
+            let counts = perft(&s, 5).unwrap();
+            assert_eq!(counts[1].paths, placements, "{name}");
+        }
+    }
+
+    #[test]
+    fn divergence_report() {
+        let d = Divergence {
+            position: State::default(),
+            m: Move::Place { x: 0, y: 1, val: 2 },
+            rules: None,
+            search: Some(State::new([[0; 4]; 4], Role::Slider, 0)),
+        };
+        assert_eq!(
+            d.to_string(),
+            "0,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 p 0 after 2@0,1: state.rs gives illegal, \
+             ai.rs gives 0,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 s 0"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+use crate::ai::{Ai, Difficulty};
+use crate::external::External;
+use crate::random::Random;
+use crate::rules::{GameRules, Standard};
+use crate::{Game, GameResult, Player, Termination};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// z value of a two-sided 95% confidence interval
+const Z_95: f64 = 1.96;
+
+// Everything needed to build a fresh player for each game
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerConfig {
+    Ai {
+        depth: i32,
+        time_limit: Option<Duration>,
+        difficulty: Difficulty,
+        // see Ai::set_spawns, empty for the smallest spawn of the rules
+        spawns: Vec<(i32, f64)>,
+    },
+    Random {
+        // empty for the smallest spawn of the rules, see Random::with_spawns
+        spawns: Vec<(i32, f64)>,
+    },
+    // another process speaking the engine protocol
+    External {
+        program: String,
+        args: Vec<String>,
+        depth: Option<i32>,
+        movetime: Option<Duration>,
+    },
+}
+
+impl PlayerConfig {
+    pub fn ai(depth: i32) -> PlayerConfig {
+        PlayerConfig::Ai {
+            depth,
+            time_limit: None,
+            difficulty: Difficulty::Adversarial,
+            spawns: Vec::new(),
+        }
+    }
+
+    // only places the rules' smallest spawn, like Random::new
+    pub fn random() -> PlayerConfig {
+        PlayerConfig::Random { spawns: Vec::new() }
+    }
+
+    // A player for games under rules. Panics if an external engine can't be
+    // started.
+    pub fn build(&self, seed: u64, rules: &Arc<dyn GameRules>) -> Box<dyn Player> {
+        match self {
+            PlayerConfig::Ai {
+                depth,
+                time_limit,
+                difficulty,
+                spawns,
+            } => {
+                let mut ai = Ai::with_rules(*depth, rules.clone());
+                ai.set_time_limit(*time_limit);
+                ai.set_difficulty(*difficulty);
+                if !spawns.is_empty() {
+                    ai.set_spawns(spawns);
+                }
+                ai.set_seed(seed);
+                Box::new(ai)
+            }
+            PlayerConfig::Random { spawns } if spawns.is_empty() => Box::new(Random::new(seed)),
+            PlayerConfig::Random { spawns } => Box::new(Random::new(seed).with_spawns(spawns)),
+            // the seed is up to the engine
+            PlayerConfig::External {
+                program,
+                args,
+                depth,
+                movetime,
+            } => match External::spawn(program, args) {
+                Ok(engine) => Box::new(engine.with_depth(*depth).with_movetime(*movetime)),
+                Err(e) => panic!("Failed to start {program}: {e}"),
+            },
+        }
+    }
+}
+
+impl fmt::Display for PlayerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlayerConfig::Ai {
+                depth,
+                time_limit,
+                difficulty,
+                spawns,
+            } => {
+                write!(f, "ai:{depth}")?;
+                if let Some(limit) = time_limit {
+                    write!(f, " time {}ms", limit.as_millis())?;
+                }
+                if *difficulty != Difficulty::Adversarial {
+                    write!(f, " {difficulty}")?;
+                }
+                write_spawns(f, spawns)
+            }
+            PlayerConfig::Random { spawns } => {
+                write!(f, "random")?;
+                write_spawns(f, spawns)
+            }
+            PlayerConfig::External { program, args, .. } => {
+                write!(f, "engine:{program}")?;
+                for arg in args {
+                    write!(f, " {arg}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn write_spawns(f: &mut fmt::Formatter, spawns: &[(i32, f64)]) -> fmt::Result {
+    if !spawns.is_empty() {
+        let spawns: Vec<String> = spawns.iter().map(|(val, w)| format!("{val}:{w}")).collect();
+        write!(f, " spawns {}", spawns.join(","))?;
+    }
+    Ok(())
+}
+
+// A statistic with its 95% confidence interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub value: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub mean: Estimate,
+    pub median: Estimate,
+    pub p10: Estimate,
+    pub p25: Estimate,
+    pub p75: Estimate,
+    pub p90: Estimate,
+}
+
+// Normal approximation for the mean, and order statistics picked by the
+// normal approximation of the binomial for the percentiles. Panics on an
+// empty sample.
+pub fn summarize(values: &[f64]) -> Summary {
+    assert!(!values.is_empty(), "Empty sample");
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let var = if values.len() > 1 {
+        values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)
+    } else {
+        0.0
+    };
+    let margin = Z_95 * (var / n).sqrt();
+
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let percentile = |q: f64| {
+        let rank = |r: f64| sorted[(r.ceil() as usize).clamp(1, sorted.len()) - 1];
+        let spread = Z_95 * (n * q * (1.0 - q)).sqrt();
+        Estimate {
+            value: rank(n * q),
+            low: rank(n * q - spread),
+            high: rank(n * q + spread),
+        }
+    };
+
+    Summary {
+        mean: Estimate {
+            value: mean,
+            low: mean - margin,
+            high: mean + margin,
+        },
+        median: percentile(0.5),
+        p10: percentile(0.1),
+        p25: percentile(0.25),
+        p75: percentile(0.75),
+        p90: percentile(0.9),
+    }
+}
+
+// All games of one config as the Slider against another as the Placer
+#[derive(Debug, Clone)]
+pub struct MatchReport {
+    pub slider: PlayerConfig,
+    pub placer: PlayerConfig,
+    // in game order, whatever order the threads finished them in
+    pub results: Vec<GameResult>,
+    // slides the Slider survived
+    pub turns: Summary,
+    pub score: Summary,
+    pub max_tile: Summary,
+    // share of games the Slider won, see GameRules::target
+    pub wins: Estimate,
+}
+
+impl MatchReport {
+    fn new(slider: &PlayerConfig, placer: &PlayerConfig, results: Vec<GameResult>) -> MatchReport {
+        let stat =
+            |f: fn(&GameResult) -> f64| summarize(&results.iter().map(f).collect::<Vec<_>>());
+        MatchReport {
+            slider: slider.clone(),
+            placer: placer.clone(),
+            turns: stat(|r| r.slider_turns as f64),
+            score: stat(|r| r.score as f64),
+            max_tile: stat(|r| r.max_tile as f64),
+            wins: stat(|r| (r.termination == Termination::Won) as u8 as f64).mean,
+            results,
+        }
+    }
+}
+
+// Runs a fixed number of games between player configurations. Game i gives
+// the Slider the seed seed + 2i and the Placer the next one, so the results
+// don't depend on the number of threads, and swapped matches are played on
+// the same seeds.
+#[derive(Debug, Clone)]
+pub struct Arena {
+    games: usize,
+    threads: usize,
+    seed: u64,
+    swap_roles: bool,
+    move_limit: Option<usize>,
+    rules: Arc<dyn GameRules>,
+}
+
+impl Arena {
+    // Panics unless games is positive
+    pub fn new(games: usize) -> Arena {
+        assert!(games > 0, "An arena needs at least one game");
+        Arena {
+            games,
+            threads: 1,
+            seed: 0,
+            swap_roles: false,
+            move_limit: None,
+            rules: Arc::new(Standard::default()),
+        }
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Arena {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Arena {
+        self.seed = seed;
+        self
+    }
+
+    // also play b as the Slider against a as the Placer
+    pub fn with_role_swap(mut self, swap_roles: bool) -> Arena {
+        self.swap_roles = swap_roles;
+        self
+    }
+
+    pub fn with_move_limit(mut self, limit: Option<usize>) -> Arena {
+        self.move_limit = limit;
+        self
+    }
+
+    // board the games are played on, 4x4 by default, under the standard
+    // rules
+    pub fn with_size(self, rows: usize, cols: usize) -> Arena {
+        self.with_rules(Arc::new(Standard::new(rows, cols)))
+    }
+
+    // rules the games are played under, the standard 4x4 ones by default
+    pub fn with_rules(mut self, rules: Arc<dyn GameRules>) -> Arena {
+        self.rules = rules;
+        self
+    }
+
+    // a as the Slider against b as the Placer, then the other way round if
+    // roles are swapped and the configs differ
+    pub fn run(&self, a: &PlayerConfig, b: &PlayerConfig) -> Vec<MatchReport> {
+        let mut reports = vec![self.run_match(a, b)];
+        if self.swap_roles && a != b {
+            reports.push(self.run_match(b, a));
+        }
+        reports
+    }
+
+    pub fn run_match(&self, slider: &PlayerConfig, placer: &PlayerConfig) -> MatchReport {
+        let next = AtomicUsize::new(0);
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(self.games) {
+                let tx = tx.clone();
+                let next = &next;
+                scope.spawn(move || loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= self.games {
+                        break;
+                    }
+                    let seed = self.seed.wrapping_add(2 * i as u64);
+                    let mut g = Game::with_rules(
+                        slider.build(seed, &self.rules),
+                        placer.build(seed.wrapping_add(1), &self.rules),
+                        self.rules.clone(),
+                    );
+                    g.set_move_limit(self.move_limit);
+                    tx.send((i, g.play())).unwrap();
+                });
+            }
+        });
+        drop(tx);
+
+        let mut results: Vec<(usize, GameResult)> = rx.into_iter().collect();
+        results.sort_by_key(|&(i, _)| i);
+        let results = results.into_iter().map(|(_, r)| r).collect();
+        MatchReport::new(slider, placer, results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let values: Vec<f64> = (1..=100).map(f64::from).collect();
+        let s = summarize(&values);
+        assert_eq!(s.mean.value, 50.5);
+        assert!(s.mean.low < 50.5 && s.mean.high > 50.5);
+        assert_eq!(s.median.value, 50.0);
+        assert_eq!((s.median.low, s.median.high), (41.0, 60.0));
+        assert_eq!((s.p10.value, s.p90.value), (10.0, 90.0));
+
+        let s = summarize(&[3.0]);
+        assert_eq!(
+            s.mean,
+            Estimate {
+                value: 3.0,
+                low: 3.0,
+                high: 3.0
+            }
+        );
+        assert_eq!(s.p90.high, 3.0);
+    }
+
+    #[test]
+    fn threads_dont_change_results() {
+        let a = PlayerConfig::random();
+        let b = PlayerConfig::ai(2);
+        let arena = Arena::new(6).with_seed(11).with_role_swap(true);
+        let serial = arena.run(&a, &b);
+        let parallel = arena.clone().with_threads(3).run(&a, &b);
+        assert_eq!(serial.len(), 2);
+        assert_eq!((&serial[1].slider, &serial[1].placer), (&b, &a));
+        for (s, p) in serial.iter().zip(&parallel) {
+            assert_eq!(s.results.len(), 6);
+            for (rs, rp) in s.results.iter().zip(&p.results) {
+                assert_eq!(rs.moves, rp.moves);
+            }
+            assert_eq!(s.turns, p.turns);
+        }
+
+        // nothing to swap between identical configs
+        assert_eq!(Arena::new(1).with_role_swap(true).run(&a, &a).len(), 1);
+    }
+}
@@ -0,0 +1,56 @@
+// A stand-in engine for testing External. It speaks just enough of the
+// engine protocol and plays the first legal move, or misbehaves as told by
+// its argument:
+//
+//   hang     never answers go
+//   crash    exits on go
+//   illegal  always answers bestmove U
+//   garbage  answers go with a bestmove that isn't a move
+//   silent   never finishes the handshake
+
+use adversarial_2048::protocol::HANDSHAKE;
+use adversarial_2048::state::{self, Move, State};
+use std::env;
+use std::io::{self, BufRead};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+fn main() {
+    let mode = env::args().nth(1).unwrap_or_default();
+    let mut s = State::default();
+    for line in io::stdin().lock().lines() {
+        let line = line.unwrap();
+        let (command, args) = line.split_once(' ').unwrap_or((&line, ""));
+        match command {
+            HANDSHAKE if mode != "silent" => {
+                println!("id name mock-engine");
+                println!("{HANDSHAKE}ok");
+            }
+            "isready" => println!("readyok"),
+            "rules" if args != "standard" => println!("error unsupported rules {args}"),
+            "position" => s = State::from_notation(args).unwrap(),
+            "go" => match mode.as_str() {
+                "hang" => thread::sleep(Duration::MAX),
+                "crash" => process::exit(1),
+                "illegal" => println!("bestmove U"),
+                "garbage" => println!("bestmove sideways"),
+                _ => match first_legal_move(&s) {
+                    Some(m) => {
+                        println!("info depth 1 score 0 pv {m}");
+                        println!("bestmove {m}");
+                    }
+                    None => println!("bestmove none"),
+                },
+            },
+            "quit" => break,
+            _ => {}
+        }
+    }
+}
+
+fn first_legal_move(s: &State) -> Option<Move> {
+    s.moves()
+        .into_iter()
+        .find(|&m| state::next_state(s, m).is_ok())
+}
@@ -0,0 +1,291 @@
+use crate::protocol::HANDSHAKE;
+use crate::state::{Direction, Move, Role, State};
+use crate::{EngineInfo, Player};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how long an engine gets to start up, and to answer on top of its movetime
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum ExternalError {
+    Spawn(io::Error),
+    Io(io::Error),
+    Timeout,
+    // the engine closed its output
+    Exited,
+    Protocol(String),
+}
+
+impl fmt::Display for ExternalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExternalError::Spawn(e) => write!(f, "couldn't start the engine: {e}"),
+            ExternalError::Io(e) => write!(f, "couldn't talk to the engine: {e}"),
+            ExternalError::Timeout => write!(f, "the engine took too long to answer"),
+            ExternalError::Exited => write!(f, "the engine exited"),
+            ExternalError::Protocol(e) => write!(f, "protocol error: {e}"),
+        }
+    }
+}
+
+impl From<io::Error> for ExternalError {
+    fn from(e: io::Error) -> ExternalError {
+        ExternalError::Io(e)
+    }
+}
+
+// A Player run by another process that speaks the engine protocol (see
+// protocol.rs) on its stdin/stdout. If the engine fails, times out or
+// answers nonsense, the player forfeits: it plays a move that's illegal in
+// any position, so the game ends with Termination::IllegalMove, and error
+// tells what went wrong.
+pub struct External {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    name: Option<String>,
+    // the rules the engine was last told to use
+    rules: String,
+    depth: Option<i32>,
+    movetime: Option<Duration>,
+    timeout: Duration,
+    last_info: Option<EngineInfo>,
+    error: Option<ExternalError>,
+}
+
+impl External {
+    // Starts the engine and completes the handshake
+    pub fn spawn(program: &str, args: &[String]) -> Result<External, ExternalError> {
+        External::spawn_with_timeout(program, args, DEFAULT_TIMEOUT)
+    }
+
+    // timeout is also used for the handshake, see with_timeout
+    pub fn spawn_with_timeout(
+        program: &str,
+        args: &[String],
+        timeout: Duration,
+    ) -> Result<External, ExternalError> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(ExternalError::Spawn)?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        // a thread per engine, so reads can time out
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = External {
+            child,
+            stdin,
+            lines,
+            name: None,
+            rules: "standard".to_string(),
+            depth: None,
+            movetime: None,
+            timeout,
+            last_info: None,
+            error: None,
+        };
+        engine.handshake()?;
+        Ok(engine)
+    }
+
+    // search depth sent with every go, instead of the engine's default
+    pub fn with_depth(mut self, depth: Option<i32>) -> External {
+        self.depth = depth;
+        self
+    }
+
+    pub fn with_movetime(mut self, movetime: Option<Duration>) -> External {
+        self.movetime = movetime;
+        self
+    }
+
+    // how much longer than the movetime the engine may take to answer
+    pub fn with_timeout(mut self, timeout: Duration) -> External {
+        self.timeout = timeout;
+        self
+    }
+
+    // the name the engine gave in the handshake
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    // why the player forfeited, if it did
+    pub fn error(&self) -> Option<&ExternalError> {
+        self.error.as_ref()
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), ExternalError> {
+        writeln!(self.stdin, "{line}")?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    fn recv(&mut self, deadline: Instant) -> Result<String, ExternalError> {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        match self.lines.recv_timeout(wait) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => Err(ExternalError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(ExternalError::Exited),
+        }
+    }
+
+    fn handshake(&mut self) -> Result<(), ExternalError> {
+        let deadline = Instant::now() + self.timeout;
+        self.send(HANDSHAKE)?;
+        loop {
+            let line = self.recv(deadline)?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                self.name = Some(name.to_string());
+            } else if line == format!("{HANDSHAKE}ok") {
+                break;
+            }
+        }
+        self.send(&format!("rules {}", self.rules))?;
+        self.send("newgame")?;
+        self.send("isready")?;
+        loop {
+            let line = self.recv(deadline)?;
+            if let Some(e) = line.strip_prefix("error ") {
+                return Err(ExternalError::Protocol(e.to_string()));
+            } else if line == "readyok" {
+                return Ok(());
+            }
+        }
+    }
+
+    fn search(&mut self, s: &State) -> Result<Move, ExternalError> {
+        let rules = s.rules().name();
+        if rules != self.rules {
+            self.send(&format!("rules {rules}"))?;
+            self.rules = rules;
+        }
+        self.send(&format!("position {}", s.to_notation()))?;
+        let mut go = "go".to_string();
+        if let Some(depth) = self.depth {
+            go += &format!(" depth {depth}");
+        }
+        if let Some(movetime) = self.movetime {
+            go += &format!(" movetime {}", movetime.as_millis());
+        }
+        self.send(&go)?;
+
+        let deadline = Instant::now() + self.movetime.unwrap_or_default() + self.timeout;
+        loop {
+            let line = self.recv(deadline)?;
+            if let Some(info) = line.strip_prefix("info ") {
+                // info lines are only for show, so bad ones are skipped
+                if let Some(info) = parse_info(info) {
+                    self.last_info = Some(info);
+                }
+            } else if let Some(m) = line.strip_prefix("bestmove ") {
+                return m
+                    .parse()
+                    .map_err(|_| ExternalError::Protocol(format!("bad bestmove {m}")));
+            } else if let Some(e) = line.strip_prefix("error ") {
+                return Err(ExternalError::Protocol(e.to_string()));
+            }
+        }
+    }
+}
+
+// "depth <n> score <value> pv <m>..."
+fn parse_info(info: &str) -> Option<EngineInfo> {
+    let mut words = info.split_whitespace();
+    let mut depth = None;
+    let mut value = None;
+    let mut pv = Vec::new();
+    while let Some(word) = words.next() {
+        match word {
+            "depth" => depth = Some(words.next()?.parse().ok()?),
+            "score" => value = Some(words.next()?.parse().ok()?),
+            "pv" => {
+                for m in words.by_ref() {
+                    pv.push(m.parse().ok()?);
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(EngineInfo {
+        depth: depth?,
+        value: value?,
+        pv,
+    })
+}
+
+// a move of the wrong kind, which no position allows
+fn forfeit(s: &State) -> Move {
+    match s.next_to_move() {
+        Role::Slider => Move::Place { x: 0, y: 0, val: 2 },
+        Role::Placer => Move::Slide(Direction::Up),
+    }
+}
+
+impl Player for External {
+    fn pick_move(&mut self, s: &State) -> Move {
+        self.last_info = None;
+        if self.error.is_some() {
+            return forfeit(s);
+        }
+        match self.search(s) {
+            Ok(m) => m,
+            Err(e) => {
+                self.error = Some(e);
+                forfeit(s)
+            }
+        }
+    }
+
+    fn engine_info(&self) -> Option<EngineInfo> {
+        self.last_info.clone()
+    }
+}
+
+impl Drop for External {
+    // ask nicely first, then kill
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let deadline = Instant::now() + Duration::from_millis(500);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_lines() {
+        let info = parse_info("depth 3 score -12 pv U 2@0,1 L").unwrap();
+        assert_eq!(info.depth, 3);
+        assert_eq!(info.value, -12);
+        assert_eq!(info.pv.len(), 3);
+        assert!(parse_info("depth 3 pv U").is_none());
+        assert!(parse_info("depth 3 score 1 nodes 5").is_none());
+    }
+}
@@ -1,70 +1,412 @@
+use crate::rules;
 use crate::state::{self, Direction, Move, Role, State};
 use crate::Player;
-use std::io;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{self, Write};
 
-pub struct Human;
+const CELL_WIDTH: u16 = 8;
+const CELL_HEIGHT: u16 = 3;
+const BOARD_TOP: u16 = 3;
 
-impl Player for Human {
-    fn pick_move(&mut self, s: &State) -> Move {
-        println!("Current state:");
-        state::print_grid(s.grid());
-
-        if s.next_to_move() == Role::Slider {
-            loop {
-                println!("Enter a direction (u/d/l/r): ");
-                let mut input = String::new();
-                io::stdin()
-                    .read_line(&mut input)
-                    .expect("Failed to read input line");
-
-                let dir = match input.trim() {
-                    "u" => Direction::Up,
-                    "d" => Direction::Down,
-                    "l" => Direction::Left,
-                    "r" => Direction::Right,
-                    _ => continue,
+// Full-screen terminal player for either role. The screen stays up between
+// moves so the opponent's replies can be watched, and is closed when the
+// game ends or the player is dropped. Quitting (q, Esc or Ctrl-C) resigns
+// the game.
+pub struct Human {
+    screen: bool,
+    cursor: (usize, usize),
+    val_idx: usize,
+}
+
+enum Action {
+    // also moves the cursor when placing
+    Slide(Direction),
+    // the spawn value typed, if there is one with a single digit
+    Value(i32),
+    Wall,
+    NextValue,
+    Place,
+    Quit,
+}
+
+impl Default for Human {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Human {
+    pub fn new() -> Human {
+        Human {
+            screen: false,
+            cursor: (0, 0),
+            val_idx: 0,
+        }
+    }
+
+    fn open_screen(&mut self) -> io::Result<()> {
+        if !self.screen {
+            execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+            self.screen = true;
+        }
+        Ok(())
+    }
+
+    fn close_screen(&mut self) -> io::Result<()> {
+        if self.screen {
+            execute!(io::stdout(), Show, LeaveAlternateScreen)?;
+            self.screen = false;
+        }
+        Ok(())
+    }
+
+    fn draw(&self, s: &State, prompt: &str, message: &str) -> io::Result<()> {
+        let mut out = io::stdout();
+        queue!(
+            out,
+            Clear(ClearType::All),
+            MoveTo(0, 0),
+            Print("Adversarial 2048"),
+            MoveTo(0, 1),
+            Print(format!("Score: {}", s.score())),
+        )?;
+
+        let grid = s.grid();
+        let placing = s.next_to_move() == Role::Placer;
+        let values = rules::tile_values(&**s.rules());
+        for (x, y) in grid.coords() {
+            let val = grid[x][y];
+            let rank = values.iter().position(|&v| v == val).map_or(0, |i| i + 1);
+            let (bg, fg) = match val {
+                state::WALL => (Color::DarkGrey, Color::DarkGrey),
+                _ => tile_colors(rank),
+            };
+            let left = y as u16 * CELL_WIDTH;
+            let top = BOARD_TOP + x as u16 * CELL_HEIGHT;
+            let selected = placing && self.cursor == (x, y);
+            for row in 0..CELL_HEIGHT {
+                let text = if row == CELL_HEIGHT / 2 && val > 0 {
+                    format!("{:^w$}", val, w = CELL_WIDTH as usize)
+                } else if row == CELL_HEIGHT / 2 && selected {
+                    let label = match self.value(s) {
+                        state::WALL => "wall".to_string(),
+                        val => val.to_string(),
+                    };
+                    format!("{:^w$}", label, w = CELL_WIDTH as usize)
+                } else {
+                    " ".repeat(CELL_WIDTH as usize)
                 };
+                let bg = if selected { Color::White } else { bg };
+                let fg = if selected { Color::DarkGrey } else { fg };
+                queue!(
+                    out,
+                    MoveTo(left, top + row),
+                    SetBackgroundColor(bg),
+                    SetForegroundColor(fg),
+                    Print(text),
+                    ResetColor,
+                )?;
+            }
+        }
 
-                let m = Move::Slide(dir);
-                if state::next_state(&s, m).is_ok() {
-                    return m;
-                }
+        let bottom = BOARD_TOP + grid.rows() as u16 * CELL_HEIGHT + 1;
+        let directions = s.rules().directions(s.phase());
+        let help = match s.next_to_move() {
+            Role::Slider if directions.len() < state::DIRECTIONS.len() => {
+                let allowed: Vec<String> = directions
+                    .iter()
+                    .map(|&d| direction_name(d).to_string())
+                    .collect();
+                format!(
+                    "arrows/wasd: slide ({} this turn)   q: quit",
+                    allowed.join(", ")
+                )
             }
-        } else {
-            loop {
-                println!("Enter a location x y: ");
-                let mut input = String::new();
-                io::stdin()
-                    .read_line(&mut input)
-                    .expect("Failed to read input line");
-
-                let mut nums = input.trim().split(' ');
-                println!("{}", input);
-                let x = match nums.next() {
-                    Some(x) => x,
-                    None => continue,
-                };
-                println!("x = {}, len = {}", x, x.len());
-                let x = match x.parse::<usize>() {
-                    Ok(x) => x,
-                    Err(_) => continue,
-                };
-                println!("{}", x);
-                let y = match nums.next() {
-                    Some(y) => y,
-                    None => continue,
-                };
-                println!("y = {}, len = {}", y, y.len());
-                let y = match y.parse::<usize>() {
-                    Ok(y) => y,
-                    Err(_) => continue,
-                };
-                println!("{}", y);
+            Role::Slider => "arrows/wasd: slide   q: quit".to_string(),
+            Role::Placer if s.walls_left() > 0 => format!(
+                "arrows/wasd: select cell   {}   b: wall ({} left)   enter: place   q: quit",
+                value_help(s.rules().spawns()),
+                s.walls_left()
+            ),
+            Role::Placer => format!(
+                "arrows/wasd: select cell   {}   enter: place   q: quit",
+                value_help(s.rules().spawns())
+            ),
+        };
+        queue!(
+            out,
+            MoveTo(0, bottom),
+            Print(prompt),
+            MoveTo(0, bottom + 1),
+            SetForegroundColor(Color::Red),
+            Print(message),
+            ResetColor,
+            MoveTo(0, bottom + 3),
+            SetForegroundColor(Color::DarkGrey),
+            Print(help),
+            ResetColor,
+        )?;
+        out.flush()
+    }
 
-                if s.grid()[x][y] == 0 {
-                    return Move::Place { x, y, val: 2 };
+    // what the Placer places: the spawns, then a wall while there are any
+    fn values(s: &State) -> Vec<i32> {
+        let mut values = s.rules().spawns().to_vec();
+        if s.walls_left() > 0 {
+            values.push(state::WALL);
+        }
+        values
+    }
+
+    // the selected value, kept in range as walls run out
+    fn value(&self, s: &State) -> i32 {
+        let values = Human::values(s);
+        values[self.val_idx % values.len()]
+    }
+
+    fn read_action(&self) -> io::Result<Option<Action>> {
+        terminal::enable_raw_mode()?;
+        let ev = event::read();
+        terminal::disable_raw_mode()?;
+        Ok(match ev? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key_action(key),
+            _ => None,
+        })
+    }
+
+    // None when the player quits
+    fn pick(&mut self, s: &State) -> io::Result<Option<Move>> {
+        self.open_screen()?;
+        let mut message = String::new();
+        loop {
+            let drops = s.rules().drops();
+            let prompt = match s.next_to_move() {
+                Role::Slider => "Your move: slide the tiles.".to_string(),
+                Role::Placer if drops > 1 => format!(
+                    "Your move: place tile {} of {drops} on an empty cell.",
+                    s.placed() + 1
+                ),
+                Role::Placer => "Your move: place a tile on an empty cell.".to_string(),
+            };
+            self.draw(s, &prompt, &message)?;
+            message.clear();
+
+            let action = match self.read_action()? {
+                Some(action) => action,
+                None => continue,
+            };
+            match (s.next_to_move(), action) {
+                (_, Action::Quit) => {
+                    self.close_screen()?;
+                    return Ok(None);
+                }
+                (Role::Slider, Action::Slide(d)) => {
+                    let m = Move::Slide(d);
+                    if state::next_state(s, m).is_ok() {
+                        return Ok(Some(m));
+                    }
+                    let name = direction_name(d);
+                    message = if s.rules().directions(s.phase()).contains(&d) {
+                        format!("Can't slide {name}: no tile would move.")
+                    } else {
+                        format!("Can't slide {name} this turn.")
+                    };
+                }
+                (Role::Placer, Action::Slide(d)) => {
+                    let (x, y) = self.cursor;
+                    self.cursor = match d {
+                        Direction::Up => (x.saturating_sub(1), y),
+                        Direction::Down => ((x + 1).min(s.grid().rows() - 1), y),
+                        Direction::Left => (x, y.saturating_sub(1)),
+                        Direction::Right => (x, (y + 1).min(s.grid().cols() - 1)),
+                    };
+                }
+                (Role::Placer, Action::Value(val)) => {
+                    if let Some(idx) = s.rules().spawns().iter().position(|&v| v == val) {
+                        self.val_idx = idx;
+                    }
+                }
+                (Role::Placer, Action::Wall) => {
+                    match Human::values(s).iter().position(|&v| v == state::WALL) {
+                        Some(idx) => self.val_idx = idx,
+                        None => message = "No walls left to place.".to_string(),
+                    }
+                }
+                (Role::Placer, Action::NextValue) => {
+                    self.val_idx = (self.val_idx + 1) % Human::values(s).len();
+                }
+                (Role::Placer, Action::Place) => {
+                    let (x, y) = self.cursor;
+                    let m = Move::Place {
+                        x,
+                        y,
+                        val: self.value(s),
+                    };
+                    if state::next_state(s, m).is_ok() {
+                        return Ok(Some(m));
+                    }
+                    message = format!(
+                        "Can't place at row {}, column {}: the cell is taken.",
+                        x + 1,
+                        y + 1
+                    );
+                }
+                (Role::Slider, _) => {
+                    message = "Use the arrow keys (or wasd) to slide.".to_string();
                 }
             }
         }
     }
 }
+
+impl Player for Human {
+    // outside Game::play there is no resigning
+    fn pick_move(&mut self, s: &State) -> Move {
+        self.pick_or_resign(s).expect("Quit outside a game")
+    }
+
+    fn pick_or_resign(&mut self, s: &State) -> Option<Move> {
+        self.pick(s).expect("Terminal error")
+    }
+
+    fn update_move(&mut self, _m: &Move, s: &State) {
+        if !self.screen {
+            return;
+        }
+        let result = if s.terminal() {
+            // leave the final position to whoever reports the game's end
+            self.close_screen()
+        } else {
+            self.draw(s, "Waiting for the other player...", "")
+        };
+        result.expect("Terminal error");
+    }
+}
+
+impl Drop for Human {
+    fn drop(&mut self) {
+        let _ = self.close_screen();
+    }
+}
+
+fn direction_name(d: Direction) -> &'static str {
+    match d {
+        Direction::Up => "up",
+        Direction::Down => "down",
+        Direction::Left => "left",
+        Direction::Right => "right",
+    }
+}
+
+fn key_action(key: KeyEvent) -> Option<Action> {
+    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+        return Some(Action::Quit);
+    }
+    let slide = |d| Some(Action::Slide(d));
+    match key.code {
+        KeyCode::Up | KeyCode::Char('w') | KeyCode::Char('k') => slide(Direction::Up),
+        KeyCode::Down | KeyCode::Char('s') | KeyCode::Char('j') => slide(Direction::Down),
+        KeyCode::Left | KeyCode::Char('a') | KeyCode::Char('h') => slide(Direction::Left),
+        KeyCode::Right | KeyCode::Char('d') | KeyCode::Char('l') => slide(Direction::Right),
+        KeyCode::Char(c @ '1'..='9') => Some(Action::Value(c as i32 - '0' as i32)),
+        KeyCode::Char('b') => Some(Action::Wall),
+        KeyCode::Tab => Some(Action::NextValue),
+        KeyCode::Enter | KeyCode::Char(' ') => Some(Action::Place),
+        KeyCode::Char('q') | KeyCode::Esc => Some(Action::Quit),
+        _ => None,
+    }
+}
+
+// The value keys for these spawns. Digit keys only pick single-digit
+// values, so larger ones can only be reached with tab.
+fn value_help(spawns: &[i32]) -> String {
+    let (digits, tab_only): (Vec<i32>, Vec<i32>) = spawns.iter().partition(|&&v| v < 10);
+    let mut help = match digits.as_slice() {
+        [] => "tab: value".to_string(),
+        _ => {
+            let digits: Vec<String> = digits.iter().map(|v| v.to_string()).collect();
+            format!("{} or tab: value", digits.join("/"))
+        }
+    };
+    if !tab_only.is_empty() && !digits.is_empty() {
+        let tab_only: Vec<String> = tab_only.iter().map(|v| v.to_string()).collect();
+        help += &format!(" (tab only for {})", tab_only.join("/"));
+    }
+    help
+}
+
+// background and text colors, roughly the original game's palette, for the
+// rank-th smallest tile the rules have (0 for an empty cell)
+fn tile_colors(rank: usize) -> (Color, Color) {
+    let dark = Color::Rgb {
+        r: 119,
+        g: 110,
+        b: 101,
+    };
+    let light = Color::Rgb {
+        r: 249,
+        g: 246,
+        b: 242,
+    };
+    let (r, g, b) = match rank {
+        0 => (205, 193, 180),
+        1 => (238, 228, 218),
+        2 => (237, 224, 200),
+        3 => (242, 177, 121),
+        4 => (245, 149, 99),
+        5 => (246, 124, 95),
+        6 => (246, 94, 59),
+        7 => (237, 207, 114),
+        8 => (237, 204, 97),
+        9 => (237, 200, 80),
+        10 => (237, 197, 63),
+        11 => (237, 194, 46),
+        _ => (60, 58, 50),
+    };
+    let fg = if rank <= 2 { dark } else { light };
+    (Color::Rgb { r, g, b }, fg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode) -> Option<Action> {
+        key_action(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn keys() {
+        assert!(matches!(
+            press(KeyCode::Left),
+            Some(Action::Slide(Direction::Left))
+        ));
+        assert!(matches!(
+            press(KeyCode::Char('w')),
+            Some(Action::Slide(Direction::Up))
+        ));
+        assert!(matches!(press(KeyCode::Char('4')), Some(Action::Value(4))));
+        assert!(matches!(press(KeyCode::Char('b')), Some(Action::Wall)));
+        assert!(matches!(press(KeyCode::Enter), Some(Action::Place)));
+        assert!(matches!(press(KeyCode::Esc), Some(Action::Quit)));
+        assert!(press(KeyCode::Char('x')).is_none());
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert!(matches!(key_action(ctrl_c), Some(Action::Quit)));
+    }
+
+    #[test]
+    fn value_keys() {
+        assert_eq!(value_help(&[2, 4]), "2/4 or tab: value");
+        assert_eq!(value_help(&[3]), "3 or tab: value");
+        assert_eq!(
+            value_help(&[2, 4, 16]),
+            "2/4 or tab: value (tab only for 16)"
+        );
+        assert_eq!(value_help(&[16, 32]), "tab: value");
+        assert!(press(KeyCode::Char('0')).is_none());
+    }
+}
@@ -1,11 +1,26 @@
 pub mod ai;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod arena;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod external;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod human;
+pub mod observer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod protocol;
 pub mod random;
-mod state;
+pub mod rules;
+pub mod solver;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sprt;
+pub mod state;
 mod utils;
 
+use observer::GameObserver;
+use rules::{GameRules, Standard};
 use state::{Move, Role, State};
-use wasm_bindgen::prelude::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
@@ -13,50 +28,383 @@ use wasm_bindgen::prelude::*;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+// What a searching player found while picking its last move
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineInfo {
+    pub depth: i32,
+    // from the point of view of the player to move
+    pub value: i32,
+    // starts with the chosen move
+    pub pv: Vec<Move>,
+}
 
 pub trait Player {
     fn pick_move(&mut self, s: &State) -> Move;
 
+    // pick_move for players that may give up instead, which ends the game
+    // with Termination::Resigned. Game::play picks moves through this.
+    fn pick_or_resign(&mut self, s: &State) -> Option<Move> {
+        Some(self.pick_move(s))
+    }
+
     fn update_move(&mut self, _m: &Move, _s: &State) {}
+
+    // called right after pick_move
+    fn engine_info(&self) -> Option<EngineInfo> {
+        None
+    }
+}
+
+// Why a game ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Termination {
+    // the Slider had no legal move left, and lost
+    NoSlides,
+    // the Slider made the rules' target tile, see GameRules::target
+    Won,
+    // the player with this role picked an illegal move and forfeits
+    IllegalMove(Role),
+    // the game reached the limit set with Game::set_move_limit
+    MoveLimit,
+    // the player with this role gave up, see Player::pick_or_resign
+    Resigned(Role),
+}
+
+#[derive(Debug, Clone)]
+pub struct GameResult {
+    pub state: State,
+    pub score: i32,
+    pub max_tile: i32,
+    pub slider_turns: usize,
+    // one per tile placed, see GameRules::drops
+    pub placer_turns: usize,
+    // every move played, starting with the Placer's first tile
+    pub moves: Vec<Move>,
+    // how long the player took to pick each move, in the same order
+    pub think_times: Vec<Duration>,
+    pub termination: Termination,
 }
 
 pub struct Game {
     slider: Box<dyn Player>,
     placer: Box<dyn Player>,
     state: State,
-    //history: Vec<State>,
+    history: Vec<Move>,
+    think_times: Vec<Duration>,
+    move_limit: Option<usize>,
+    observers: Vec<Box<dyn GameObserver>>,
 }
 
 impl Game {
     pub fn new(slider: Box<dyn Player>, placer: Box<dyn Player>) -> Game {
+        Game::with_size(slider, placer, state::ROWS, state::COLS)
+    }
+
+    // a standard game starting from an empty rows x cols board
+    pub fn with_size(
+        slider: Box<dyn Player>,
+        placer: Box<dyn Player>,
+        rows: usize,
+        cols: usize,
+    ) -> Game {
+        Game::with_rules(slider, placer, Arc::new(Standard::new(rows, cols)))
+    }
+
+    // a game under rules, starting from their empty board
+    pub fn with_rules(
+        slider: Box<dyn Player>,
+        placer: Box<dyn Player>,
+        rules: Arc<dyn GameRules>,
+    ) -> Game {
         Game {
             slider,
             placer,
-            state: state::INITIAL_STATE,
-            //history: Vec::new(),
+            state: State::start(rules),
+            history: Vec::new(),
+            think_times: Vec::new(),
+            move_limit: None,
+            observers: Vec::new(),
         }
     }
 
-    pub fn play(&mut self) {
-        while !self.state.terminal() {
-            let m = if self.state.next_to_move() == Role::Slider {
-                state::print_grid(self.state.grid());
-                self.slider.pick_move(&self.state)
-            } else {
-                self.placer.pick_move(&self.state)
+    // stop the game after this many moves of both players together
+    pub fn set_move_limit(&mut self, limit: Option<usize>) {
+        self.move_limit = limit;
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn GameObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    // every move played so far, starting with the Placer's first tile
+    pub fn moves(&self) -> &[Move] {
+        &self.history
+    }
+
+    pub fn play(&mut self) -> GameResult {
+        for o in &mut self.observers {
+            o.on_game_start(&self.state);
+        }
+        let termination = loop {
+            if self.state.won() {
+                break Termination::Won;
+            }
+            if self.state.terminal() {
+                break Termination::NoSlides;
+            }
+            if self
+                .move_limit
+                .is_some_and(|limit| self.history.len() >= limit)
+            {
+                break Termination::MoveLimit;
+            }
+            let role = self.state.next_to_move();
+            let player = match role {
+                Role::Slider => &mut self.slider,
+                Role::Placer => &mut self.placer,
+            };
+            let start = Instant::now();
+            let Some(m) = player.pick_or_resign(&self.state) else {
+                break Termination::Resigned(role);
             };
-            let s = state::next_state(&self.state, m).unwrap();
-            //self.history.push(old_state);
+            let think_time = start.elapsed();
+            if let Some(info) = player.engine_info() {
+                for o in &mut self.observers {
+                    o.on_engine_info(role, &info);
+                }
+            }
+            let s = match state::next_state(&self.state, m) {
+                Ok(s) => s,
+                Err(_) => break Termination::IllegalMove(role),
+            };
+            self.history.push(m);
+            self.think_times.push(think_time);
             self.slider.update_move(&m, &s);
             self.placer.update_move(&m, &s);
+            for o in &mut self.observers {
+                o.on_move(&m, &s);
+            }
             self.state = s;
+        };
+        for o in &mut self.observers {
+            o.on_game_end(&self.state);
+        }
+        self.result(termination)
+    }
+
+    fn result(&self, termination: Termination) -> GameResult {
+        let placer_turns = self
+            .history
+            .iter()
+            .filter(|m| matches!(m, Move::Place { .. }))
+            .count();
+        GameResult {
+            state: self.state.clone(),
+            score: self.state.score(),
+            max_tile: self.state.max_tile(),
+            slider_turns: self.history.len() - placer_turns,
+            placer_turns,
+            moves: self.history.clone(),
+            think_times: self.think_times.clone(),
+            termination,
         }
-        println!("Game over! Score = {}, Final state =", self.state.score());
-        state::print_grid(self.state.grid());
     }
 }
 
-#[wasm_bindgen]
-pub fn greet() {
-    println!("Hi");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use random::Random;
+    use state::Direction;
+
+    // always slides up, legal or not
+    struct Stubborn;
+
+    impl Player for Stubborn {
+        fn pick_move(&mut self, _s: &State) -> Move {
+            Move::Slide(Direction::Up)
+        }
+    }
+
+    #[test]
+    fn result_of_finished_game() {
+        let mut g = Game::new(Box::new(Random::new(1)), Box::new(Random::new(2)));
+        let r = g.play();
+        assert_eq!(r.termination, Termination::NoSlides);
+        assert!(r.state.terminal());
+        assert_eq!(r.score, r.state.score());
+        assert_eq!(r.moves, g.moves());
+        assert_eq!(r.think_times.len(), r.moves.len());
+        // the Placer moves first and the Slider is stuck at the end
+        assert_eq!(r.placer_turns, r.slider_turns + 1);
+        assert_eq!(r.slider_turns + r.placer_turns, r.moves.len());
+        assert!(r.max_tile >= 4);
+    }
+
+    // gives up straight away
+    struct Quitter;
+
+    impl Player for Quitter {
+        fn pick_move(&mut self, _s: &State) -> Move {
+            unreachable!()
+        }
+
+        fn pick_or_resign(&mut self, _s: &State) -> Option<Move> {
+            None
+        }
+    }
+
+    #[test]
+    fn result_of_cut_short_game() {
+        let mut g = Game::new(Box::new(Stubborn), Box::new(Random::new(3)));
+        let r = g.play();
+        // sliding up becomes illegal once the tiles are packed at the top
+        assert!(matches!(
+            r.termination,
+            Termination::IllegalMove(Role::Slider)
+        ));
+        assert!(!r.state.terminal());
+
+        let mut g = Game::new(Box::new(Random::new(1)), Box::new(Random::new(2)));
+        g.set_move_limit(Some(5));
+        let r = g.play();
+        assert_eq!(r.termination, Termination::MoveLimit);
+        assert_eq!((r.placer_turns, r.slider_turns), (3, 2));
+
+        let mut g = Game::new(Box::new(Quitter), Box::new(Random::new(4)));
+        let r = g.play();
+        assert_eq!(r.termination, Termination::Resigned(Role::Slider));
+        assert_eq!((r.placer_turns, r.slider_turns), (1, 0));
+    }
+
+    #[test]
+    fn game_on_small_board() {
+        let mut g = Game::with_size(
+            Box::new(ai::Ai::with_size(6, 2, 3)),
+            Box::new(Random::new(4)),
+            2,
+            3,
+        );
+        let r = g.play();
+        assert_eq!(r.termination, Termination::NoSlides);
+        assert_eq!((r.state.grid().rows(), r.state.grid().cols()), (2, 3));
+    }
+
+    // 3s are placed and equal tiles merge into their sum, so every tile is
+    // 3 times a power of 2
+    #[derive(Debug)]
+    struct Triples;
+
+    impl GameRules for Triples {
+        fn name(&self) -> String {
+            "triples".to_string()
+        }
+
+        fn rows(&self) -> usize {
+            3
+        }
+
+        fn cols(&self) -> usize {
+            3
+        }
+
+        fn spawns(&self) -> &[i32] {
+            &[3]
+        }
+
+        fn merge(&self, a: i32, b: i32) -> Option<i32> {
+            (a == b).then_some(a + b)
+        }
+    }
+
+    #[test]
+    fn game_under_other_rules() {
+        let rules: Arc<dyn GameRules> = Arc::new(Triples);
+        let mut g = Game::with_rules(
+            Box::new(ai::Ai::with_rules(4, rules.clone())),
+            Box::new(Random::new(5)),
+            rules.clone(),
+        );
+        let r = g.play();
+        assert_eq!(r.termination, Termination::NoSlides);
+        let values = rules::tile_values(&*rules);
+        assert!(r.state.grid().coords().all(|(i, j)| {
+            let val = r.state.grid()[i][j];
+            val == 0 || values.contains(&val)
+        }));
+        assert!(r.max_tile >= 6);
+
+        let mut g = Game::with_rules(
+            Box::new(Random::new(6)),
+            Box::new(ai::Ai::with_rules(4, rules.clone())),
+            rules,
+        );
+        assert_eq!(g.play().termination, Termination::NoSlides);
+    }
+
+    #[test]
+    fn games_under_variants() {
+        // rules, board size, whether the AI slides (or places), random seed,
+        // how the game ends
+        let cases = [
+            ("fibonacci", 3, true, 7, Termination::NoSlides),
+            ("threes", 3, true, 7, Termination::NoSlides),
+            (
+                "standard+blocked:1,1+walls:2",
+                3,
+                false,
+                8,
+                Termination::NoSlides,
+            ),
+            (
+                "standard+slides:DLR,UDL,ULR,UDR",
+                3,
+                true,
+                9,
+                Termination::NoSlides,
+            ),
+            (
+                "standard+slides:DLR,UDL,ULR,UDR",
+                3,
+                false,
+                10,
+                Termination::NoSlides,
+            ),
+            ("standard+drops:2", 3, true, 11, Termination::NoSlides),
+            ("standard+drops:2", 3, false, 12, Termination::NoSlides),
+            ("standard+target:64", 4, true, 13, Termination::Won),
+        ];
+        for (name, size, ai_slides, seed, termination) in cases {
+            let rules = rules::by_name(name, size, size).unwrap();
+            let spawns: Vec<(i32, f64)> = rules.spawns().iter().map(|&val| (val, 1.0)).collect();
+            let ai: Box<dyn Player> = Box::new(ai::Ai::with_rules(4, rules.clone()));
+            let random = Box::new(Random::new(seed).with_spawns(&spawns));
+            let mut g = match ai_slides {
+                true => Game::with_rules(ai, random, rules.clone()),
+                false => Game::with_rules(random, ai, rules.clone()),
+            };
+            let r = g.play();
+            assert_eq!(r.termination, termination, "{name}");
+            assert!(r.state.terminal(), "{name}");
+            // a searching Slider gets past the spawned tiles
+            let max_spawn = *rules.spawns().iter().max().unwrap();
+            assert!(!ai_slides || r.max_tile > max_spawn, "{name}");
+
+            // the moves replay to the final position under the same rules
+            let mut s = State::start(rules.clone());
+            for &m in &r.moves {
+                s = state::next_state(&s, m).unwrap();
+            }
+            assert_eq!(s, r.state, "{name}");
+            let values = rules::tile_values(&*rules);
+            assert!(r.state.grid().coords().all(|(i, j)| {
+                let val = r.state.grid()[i][j];
+                val == 0 || val == state::WALL || values.contains(&val)
+            }));
+        }
+    }
 }
@@ -1,9 +1,749 @@
-#[allow(unused_imports)]
-use adversarial_2048::{ai::Ai, human::Human, random::Random, Game};
+use adversarial_2048::ai::{self, Ai, Difficulty};
+use adversarial_2048::arena::{Arena, Estimate, MatchReport, PlayerConfig, Summary};
+use adversarial_2048::external::External;
+use adversarial_2048::human::Human;
+use adversarial_2048::observer::{PrettyPrinter, RecordWriter};
+use adversarial_2048::protocol::Server;
+use adversarial_2048::random::{Random, CLASSIC_SPAWNS};
+use adversarial_2048::rules::{self, GameRules};
+use adversarial_2048::solver::{self, Objective, Perfect, Solution, TableError};
+use adversarial_2048::sprt::{Sprt, SprtOutcome};
+use adversarial_2048::state::{self, Role, State};
+use adversarial_2048::{Game, Player, Termination};
+use std::cell::OnceCell;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage:
+  adversarial-2048 play [options]                human Slider against the engine
+  adversarial-2048 selfplay [options]            engine against engine
+  adversarial-2048 analyze <position> [options]  score every move of a position
+  adversarial-2048 arena [options]               statistics over many games
+  adversarial-2048 sprt [options]                test a candidate against a
+                                                 baseline config
+  adversarial-2048 perft <position> [options]    count positions to --depth
+                                                 plies (4), checking the
+                                                 search's rules
+  adversarial-2048 engine [options]              serve the engine protocol on
+                                                 stdin/stdout, under --rules
+                                                 until a rules command
+  adversarial-2048 solve [options]               solve a small board exactly
+
+options:
+  --slider <player>     ai[:depth], random, human, engine:<command>, an
+                        external engine speaking the engine protocol, or
+                        perfect[:<objective>] on a solved board, playing
+                        for turns (the default), tile or score
+  --placer <player>     ai[:depth], random, human, engine:<command> or
+                        perfect[:<objective>]
+  --depth <n>           search depth of ai players that don't give one
+  --time <ms>           search each move by time instead of depth (not for
+                        analyze)
+  --difficulty <spec>   Placer strength: adversarial, softmax:<t>, epsilon:<p>,
+                        every:<k> or mixed:<p>, adversarial with chance p
+                        and random otherwise, which ai players on either
+                        side also search against (0 for expectimax)
+  --seed <n>            seed for the players' random choices (random by
+                        default, and written to the record either way)
+  --spawns <dist>       tiles placed by a random Placer, or randomly by an
+                        ai one: classic (90% 2s, 10% 4s) or weighted values
+                        like 2:3,4:1 (the smallest tile the rules place)
+  --rules <name>        rule variant, also for the positions given to
+                        analyze and perft: standard (the default), or
+                        standard:<values> placing other tiles than 2 and 4,
+                        like standard:2 or standard:2,4,8, fibonacci
+                        (neighbouring Fibonacci numbers merge) or threes
+                        (1 and 2 make 3, lines move one cell at a time).
+                        Any of them takes +blocked:<x>,<y>;... for cells
+                        walled from the start, +walls:<n> for walls the
+                        Placer may place (as W@<x>,<y>), +drops:<k> for k
+                        tiles placed per turn, +slides:<dirs>,... for the
+                        directions the Slider may slide in, turn after
+                        turn, and +target:<tile> for a tile the Slider wins
+                        by making, e.g. standard+blocked:1,1+walls:2,
+                        standard+drops:2, standard+slides:UDL (never right),
+                        standard+slides:DLR,UDL,ULR,UDR (a direction banned
+                        in turn) or standard+target:2048. Positions give
+                        the turn's index after the score under +slides,
+                        then the tiles placed so far this turn under +drops
+  --size <rows>x<cols>  board size (4x4), taken from the position for
+                        analyze
+  --table <path>        solver table for perfect players and solve, read
+                        if it exists and written otherwise
+  --record <path>       write the game's moves to a file
+  --cache <path>        start the engine from a search cache, analyze also
+                        writes it back
+  --games <n>           games the arena plays (100), or the most pairs of
+                        games sprt plays (1000)
+  --threads <n>         threads the arena and sprt play on (all cores)
+  --swap                the arena also plays the Slider's config as the
+                        Placer and the other way round
+  --role <role>         role sprt tests: slider (the default) or placer,
+                        with --slider or --placer giving the opponent
+  --baseline <player>   ai[:depth], random or engine:<command> (ai)
+  --candidate <player>  ai[:depth], random or engine:<command> (ai)
+  --delta [<d0>,]<d1>   sprt accepts the candidate when it survives d1 more
+                        turns (fewer for a Placer) rather than d0 (0)
+  --alpha <p>           chance to accept a candidate that's only d0 better
+                        (0.05)
+  --beta <p>            chance to reject one that's d1 better (0.05)
+
+positions are written like \"2,0,0,0/0,0,0,0/0,0,4,0/0,0,0,0 s\": rows
+separated by '/', 0 for empty cells, then s or p for the side to move.
+perft also takes startpos for the empty board";
+
+const SLIDER_DEPTH: i32 = 13;
+const PLACER_DEPTH: i32 = 11;
+
+#[derive(Clone, Debug)]
+enum PlayerKind {
+    Ai(Option<i32>),
+    Random,
+    Human,
+    // program and arguments
+    External(Vec<String>),
+    Perfect(Objective),
+}
+
+impl fmt::Display for PlayerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlayerKind::Ai(Some(depth)) => write!(f, "ai:{depth}"),
+            PlayerKind::Ai(None) => write!(f, "ai"),
+            PlayerKind::Random => write!(f, "random"),
+            PlayerKind::Human => write!(f, "human"),
+            PlayerKind::External(command) => write!(f, "engine:{}", command.join(" ")),
+            PlayerKind::Perfect(objective) => write!(f, "perfect:{objective}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Command {
+    Play,
+    SelfPlay,
+    Analyze(State),
+    Arena,
+    Sprt,
+    Engine,
+    // None for startpos, which depends on --size
+    Perft(Option<State>),
+    Solve,
+}
+
+#[derive(Clone)]
+struct Options {
+    command: Command,
+    slider: PlayerKind,
+    placer: PlayerKind,
+    depth: Option<i32>,
+    time: Option<Duration>,
+    difficulty: Difficulty,
+    seed: u64,
+    spawns: Vec<(i32, f64)>,
+    record: Option<String>,
+    cache: Option<String>,
+    table: Option<String>,
+    size: (usize, usize),
+    rules: Arc<dyn GameRules>,
+    games: Option<usize>,
+    threads: usize,
+    swap: bool,
+    role: Role,
+    baseline: PlayerKind,
+    candidate: PlayerKind,
+    delta: (f64, f64),
+    alpha: f64,
+    beta: f64,
+}
+
+fn parse_player(spec: &str) -> Result<PlayerKind, String> {
+    match spec.split_once(':') {
+        Some(("ai", depth)) => match depth.parse() {
+            Ok(depth) if depth > 0 => Ok(PlayerKind::Ai(Some(depth))),
+            _ => Err(format!("invalid search depth in \"{spec}\"")),
+        },
+        Some(("engine", command)) => {
+            let command: Vec<String> = command.split_whitespace().map(String::from).collect();
+            if command.is_empty() {
+                return Err(format!("missing engine command in \"{spec}\""));
+            }
+            Ok(PlayerKind::External(command))
+        }
+        Some(("perfect", objective)) => match objective.parse() {
+            Ok(objective) => Ok(PlayerKind::Perfect(objective)),
+            Err(_) => Err(format!("unknown objective in \"{spec}\"")),
+        },
+        None if spec == "perfect" => Ok(PlayerKind::Perfect(Objective::Turns)),
+        None if spec == "ai" => Ok(PlayerKind::Ai(None)),
+        None if spec == "random" => Ok(PlayerKind::Random),
+        None if spec == "human" => Ok(PlayerKind::Human),
+        _ => Err(format!("unknown player \"{spec}\"")),
+    }
+}
+
+fn parse_spawns(spec: &str) -> Option<Vec<(i32, f64)>> {
+    if spec == "classic" {
+        return Some(CLASSIC_SPAWNS.to_vec());
+    }
+    let mut spawns = Vec::new();
+    for part in spec.split(',') {
+        let (val, weight): (i32, f64) = match part.split_once(':') {
+            Some((val, weight)) => (val.parse().ok()?, weight.parse().ok()?),
+            None => (part.parse().ok()?, 1.0),
+        };
+        if val <= 0 || weight.is_nan() || weight < 0.0 {
+            return None;
+        }
+        spawns.push((val, weight));
+    }
+    if spawns.iter().all(|&(_, w)| w == 0.0) {
+        return None;
+    }
+    Some(spawns)
+}
+
+// "<rows>x<cols>", small enough for the search
+fn parse_size(spec: &str) -> Option<(usize, usize)> {
+    let (rows, cols) = spec.split_once('x')?;
+    let (rows, cols): (usize, usize) = (rows.parse().ok()?, cols.parse().ok()?);
+    (rows > 0 && cols > 0 && rows * cols <= ai::MAX_CELLS).then_some((rows, cols))
+}
+
+fn parse_delta(spec: &str) -> Option<(f64, f64)> {
+    let (d0, d1) = match spec.split_once(',') {
+        Some((d0, d1)) => (d0.parse().ok()?, d1.parse().ok()?),
+        None => (0.0, spec.parse().ok()?),
+    };
+    (d0 < d1).then_some((d0, d1))
+}
+
+fn parse_probability(spec: &str) -> Option<f64> {
+    let p: f64 = spec.parse().ok()?;
+    (p > 0.0 && p < 1.0).then_some(p)
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut args = args.iter();
+    // positions are read once --rules is known
+    let mut position = None;
+    let (command, slider, placer) = match args.next().map(String::as_str) {
+        Some("play") => (Command::Play, PlayerKind::Human, PlayerKind::Ai(None)),
+        Some("selfplay") => (
+            Command::SelfPlay,
+            PlayerKind::Ai(None),
+            PlayerKind::Ai(None),
+        ),
+        Some("analyze") => {
+            position = Some(args.next().ok_or("analyze needs a position")?);
+            (
+                Command::Analyze(State::default()),
+                PlayerKind::Ai(None),
+                PlayerKind::Ai(None),
+            )
+        }
+        Some("arena") => (Command::Arena, PlayerKind::Ai(None), PlayerKind::Random),
+        Some("perft") => {
+            position = Some(args.next().ok_or("perft needs a position")?)
+                .filter(|&position| position != "startpos");
+            (
+                Command::Perft(None),
+                PlayerKind::Ai(None),
+                PlayerKind::Ai(None),
+            )
+        }
+        Some("engine") => (Command::Engine, PlayerKind::Ai(None), PlayerKind::Ai(None)),
+        Some("sprt") => (Command::Sprt, PlayerKind::Random, PlayerKind::Random),
+        Some("solve") => (Command::Solve, PlayerKind::Ai(None), PlayerKind::Ai(None)),
+        Some(command) => return Err(format!("unknown command \"{command}\"")),
+        None => return Err("missing command".to_string()),
+    };
+    let mut opts = Options {
+        command,
+        slider,
+        placer,
+        depth: None,
+        time: None,
+        difficulty: Difficulty::Adversarial,
+        seed: rand::random(),
+        // the rules' smallest spawn unless given
+        spawns: Vec::new(),
+        record: None,
+        cache: None,
+        table: None,
+        size: (state::ROWS, state::COLS),
+        rules: Arc::new(rules::Standard::default()),
+        games: None,
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        swap: false,
+        role: Role::Slider,
+        baseline: PlayerKind::Ai(None),
+        candidate: PlayerKind::Ai(None),
+        delta: (0.0, 10.0),
+        alpha: 0.05,
+        beta: 0.05,
+    };
+    let mut rules_name = "standard".to_string();
+
+    while let Some(flag) = args.next() {
+        if flag == "--swap" {
+            opts.swap = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {flag}"))?;
+        let invalid = || format!("invalid value for {flag}: \"{value}\"");
+        match flag.as_str() {
+            "--slider" => opts.slider = parse_player(value)?,
+            "--placer" => opts.placer = parse_player(value)?,
+            "--depth" => match value.parse() {
+                Ok(depth) if depth > 0 => opts.depth = Some(depth),
+                _ => return Err(invalid()),
+            },
+            "--time" => {
+                let ms = value.parse().map_err(|_| invalid())?;
+                opts.time = Some(Duration::from_millis(ms));
+            }
+            "--difficulty" => opts.difficulty = value.parse().map_err(|_| invalid())?,
+            "--seed" => opts.seed = value.parse().map_err(|_| invalid())?,
+            "--spawns" => opts.spawns = parse_spawns(value).ok_or_else(invalid)?,
+            "--rules" => rules_name = value.clone(),
+            "--record" => opts.record = Some(value.clone()),
+            "--cache" => opts.cache = Some(value.clone()),
+            "--table" => opts.table = Some(value.clone()),
+            "--size" => opts.size = parse_size(value).ok_or_else(invalid)?,
+            "--games" => match value.parse() {
+                Ok(n) if n > 0 => opts.games = Some(n),
+                _ => return Err(invalid()),
+            },
+            "--threads" => match value.parse() {
+                Ok(n) if n > 0 => opts.threads = n,
+                _ => return Err(invalid()),
+            },
+            "--role" => match value.as_str() {
+                "slider" => opts.role = Role::Slider,
+                "placer" => opts.role = Role::Placer,
+                _ => return Err(invalid()),
+            },
+            "--baseline" => opts.baseline = parse_player(value)?,
+            "--candidate" => opts.candidate = parse_player(value)?,
+            "--delta" => opts.delta = parse_delta(value).ok_or_else(invalid)?,
+            "--alpha" => opts.alpha = parse_probability(value).ok_or_else(invalid)?,
+            "--beta" => opts.beta = parse_probability(value).ok_or_else(invalid)?,
+            _ => return Err(format!("unknown option {flag}")),
+        }
+    }
+    if let Some(position) = position {
+        let s = State::from_notation_with(position, &rules_name)
+            .map_err(|_| format!("invalid position \"{position}\""))?;
+        opts.size = (s.grid().rows(), s.grid().cols());
+        if opts.size.0 * opts.size.1 > ai::MAX_CELLS {
+            return Err(format!(
+                "position \"{position}\" has more than the {} cells the search handles",
+                ai::MAX_CELLS
+            ));
+        }
+        opts.command = match opts.command {
+            Command::Analyze(_) => Command::Analyze(s),
+            _ => Command::Perft(Some(s)),
+        };
+    }
+    if matches!(opts.command, Command::Analyze(_)) && opts.time.is_some() {
+        return Err("analyze searches to --depth, not for --time".to_string());
+    }
+    let (rows, cols) = opts.size;
+    opts.rules = rules::by_name(&rules_name, rows, cols)
+        .ok_or_else(|| format!("unknown rule variant \"{rules_name}\""))?;
+    if let Some(&(val, _)) = opts
+        .spawns
+        .iter()
+        .find(|(val, _)| !opts.rules.spawns().contains(val))
+    {
+        return Err(format!("the {rules_name} rules don't place {val}s"));
+    }
+    Ok(opts)
+}
+
+fn make_ai(opts: &Options, depth: Option<i32>, default_depth: i32, seed: u64) -> Ai {
+    let depth = depth.or(opts.depth).unwrap_or(default_depth);
+    let mut ai = Ai::with_rules(depth, opts.rules.clone());
+    ai.set_time_limit(opts.time);
+    ai.set_seed(seed);
+    // before loading, as cached values depend on them
+    ai.set_difficulty(opts.difficulty);
+    if !opts.spawns.is_empty() {
+        ai.set_spawns(&opts.spawns);
+    }
+    if let Some(path) = &opts.cache {
+        if let Err(e) = ai.load_cache(path) {
+            eprintln!("not using search cache {path}: {e}");
+        }
+    }
+    ai
+}
+
+// The solved --size board under --rules, from --table if it holds one, and
+// saved there if it doesn't
+fn solution(opts: &Options) -> Result<Solution, String> {
+    let (rows, cols) = opts.size;
+    let name = opts.rules.name();
+    if let Some(path) = &opts.table {
+        match Solution::load(path) {
+            Ok(s) if (s.rows(), s.cols(), s.rules().name()) == (rows, cols, name.clone()) => {
+                return Ok(s)
+            }
+            Ok(s) => eprintln!(
+                "table {path} is for {}x{} {}, solving",
+                s.rows(),
+                s.cols(),
+                s.rules().name()
+            ),
+            Err(TableError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("not using table {path}: {e}"),
+        }
+    }
+    if !Solution::fits(&*opts.rules) {
+        return Err(format!(
+            "a {rows}x{cols} {name} board is too large to solve (at most {} cells \
+             for the standard rules)",
+            solver::MAX_CELLS
+        ));
+    }
+    let s = Solution::solve(opts.rules.clone());
+    if let Some(path) = &opts.table {
+        if let Err(e) = s.save(path) {
+            eprintln!("failed to save table {path}: {e}");
+        }
+    }
+    Ok(s)
+}
+
+// table is shared by both players, and only solved if one of them needs it
+fn make_player(
+    opts: &Options,
+    kind: &PlayerKind,
+    default_depth: i32,
+    seed: u64,
+    table: &OnceCell<Arc<Solution>>,
+) -> Box<dyn Player> {
+    match kind {
+        PlayerKind::Ai(depth) => Box::new(make_ai(opts, *depth, default_depth, seed)),
+        PlayerKind::Random if opts.spawns.is_empty() => Box::new(Random::new(seed)),
+        PlayerKind::Random => Box::new(Random::new(seed).with_spawns(&opts.spawns)),
+        PlayerKind::Human => Box::new(Human::new()),
+        PlayerKind::External(command) => match External::spawn(&command[0], &command[1..]) {
+            Ok(engine) => Box::new(engine.with_depth(opts.depth).with_movetime(opts.time)),
+            Err(e) => {
+                eprintln!("{}: {e}", command[0]);
+                process::exit(1);
+            }
+        },
+        PlayerKind::Perfect(objective) => {
+            let solution = table.get_or_init(|| match solution(opts) {
+                Ok(s) => Arc::new(s),
+                Err(e) => {
+                    eprintln!("{e}");
+                    process::exit(2);
+                }
+            });
+            Box::new(Perfect::new(solution.clone(), *objective))
+        }
+    }
+}
+
+fn play(opts: &Options) {
+    // the two players get different seeds so their choices aren't correlated
+    let table = OnceCell::new();
+    let slider = make_player(opts, &opts.slider, SLIDER_DEPTH, opts.seed, &table);
+    let placer = make_player(
+        opts,
+        &opts.placer,
+        PLACER_DEPTH,
+        opts.seed.wrapping_add(1),
+        &table,
+    );
+    let mut g = Game::with_rules(slider, placer, opts.rules.clone());
+
+    // the terminal UI draws the game itself
+    let human =
+        matches!(opts.slider, PlayerKind::Human) || matches!(opts.placer, PlayerKind::Human);
+    if human {
+        g.add_observer(Box::new(PrettyPrinter::end_only()));
+    } else {
+        g.add_observer(Box::new(PrettyPrinter::new()));
+    }
+    if let Some(path) = &opts.record {
+        match File::create(path) {
+            Ok(f) => {
+                let header = record_header(opts);
+                g.add_observer(Box::new(RecordWriter::new(BufWriter::new(f), &header)));
+            }
+            Err(e) => eprintln!("failed to create record {path}: {e}"),
+        }
+    }
+    let result = g.play();
+    match result.termination {
+        Termination::IllegalMove(role) => {
+            eprintln!("{role:?} played an illegal move and forfeits")
+        }
+        Termination::Resigned(role) => println!("{role:?} quit."),
+        _ => {}
+    }
+}
+
+// Everything that decides the game's moves, so that playing it again with
+// the same options replays it
+fn record_header(opts: &Options) -> Vec<(&'static str, String)> {
+    // the depth ai players actually search to
+    let player = |kind: &PlayerKind, default_depth| match kind {
+        PlayerKind::Ai(depth) => {
+            PlayerKind::Ai(Some(depth.or(opts.depth).unwrap_or(default_depth)))
+        }
+        kind => kind.clone(),
+    };
+    let (rows, cols) = opts.size;
+    let spawns: Vec<String> = opts
+        .spawns
+        .iter()
+        .map(|(val, w)| format!("{val}:{w}"))
+        .collect();
+    let mut header = vec![
+        ("slider", player(&opts.slider, SLIDER_DEPTH).to_string()),
+        ("placer", player(&opts.placer, PLACER_DEPTH).to_string()),
+        ("seed", opts.seed.to_string()),
+        ("rules", opts.rules.name()),
+        ("size", format!("{rows}x{cols}")),
+        ("difficulty", opts.difficulty.to_string()),
+    ];
+    if !spawns.is_empty() {
+        header.push(("spawns", spawns.join(",")));
+    }
+    if let Some(time) = opts.time {
+        header.push(("time", format!("{}ms", time.as_millis())));
+    }
+    header
+}
+
+fn analyze(opts: &Options, s: &State) -> Result<(), String> {
+    let mut ai = make_ai(opts, None, SLIDER_DEPTH, opts.seed);
+    let depth = opts.depth.unwrap_or(SLIDER_DEPTH);
+    let root_moves = ai
+        .analyze(s, depth)
+        .map_err(|e| format!("can't analyze the position: {e:?}"))?;
+    for rm in root_moves {
+        let pv: Vec<String> = rm.pv.iter().map(|m| m.to_string()).collect();
+        let death = if rm.proven_death {
+            " (proven death)"
+        } else if rm.proven_win {
+            " (proven win)"
+        } else {
+            ""
+        };
+        println!(
+            "{:>8} {:>12}{death}  pv {}",
+            rm.m.to_string(),
+            rm.value,
+            pv.join(" ")
+        );
+    }
+    if let Some(path) = &opts.cache {
+        if let Err(e) = ai.save_cache(path) {
+            eprintln!("failed to save search cache {path}: {e}");
+        }
+    }
+    Ok(())
+}
+
+fn player_config(
+    opts: &Options,
+    kind: &PlayerKind,
+    default_depth: i32,
+) -> Result<PlayerConfig, String> {
+    match kind {
+        PlayerKind::Ai(depth) => Ok(PlayerConfig::Ai {
+            depth: depth.or(opts.depth).unwrap_or(default_depth),
+            time_limit: opts.time,
+            difficulty: opts.difficulty,
+            spawns: opts.spawns.clone(),
+        }),
+        PlayerKind::Random => Ok(PlayerConfig::Random {
+            spawns: opts.spawns.clone(),
+        }),
+        PlayerKind::Human => Err("the arena can't play human players".to_string()),
+        PlayerKind::Perfect(_) => Err("the arena can't play perfect players".to_string()),
+        PlayerKind::External(command) => Ok(PlayerConfig::External {
+            program: command[0].clone(),
+            args: command[1..].to_vec(),
+            depth: opts.depth,
+            movetime: opts.time,
+        }),
+    }
+}
+
+fn print_summary(name: &str, summary: &Summary) {
+    let line = |label: &str, e: &Estimate| {
+        println!(
+            "  {label:<7}{:>10.1}  [{:.1}, {:.1}]",
+            e.value, e.low, e.high
+        );
+    };
+    println!("{name}");
+    line("mean", &summary.mean);
+    line("p10", &summary.p10);
+    line("p25", &summary.p25);
+    line("median", &summary.median);
+    line("p75", &summary.p75);
+    line("p90", &summary.p90);
+}
+
+fn print_report(report: &MatchReport) {
+    println!(
+        "Slider {} vs Placer {}: {} games",
+        report.slider,
+        report.placer,
+        report.results.len()
+    );
+    print_summary("survival turns", &report.turns);
+    print_summary("score", &report.score);
+    print_summary("max tile", &report.max_tile);
+    if report.results[0].state.rules().target().is_some() {
+        let wins = &report.wins;
+        println!(
+            "win rate {:.3}  [{:.3}, {:.3}]",
+            wins.value, wins.low, wins.high
+        );
+    }
+}
+
+fn arena(opts: &Options) -> Result<(), String> {
+    let a = player_config(opts, &opts.slider, SLIDER_DEPTH)?;
+    let b = player_config(opts, &opts.placer, PLACER_DEPTH)?;
+    let arena = Arena::new(opts.games.unwrap_or(100))
+        .with_threads(opts.threads)
+        .with_seed(opts.seed)
+        .with_role_swap(opts.swap)
+        .with_rules(opts.rules.clone());
+    println!("seed {}", opts.seed);
+    for report in arena.run(&a, &b) {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+fn sprt(opts: &Options) -> Result<(), String> {
+    let (depth, opponent) = match opts.role {
+        Role::Slider => (
+            SLIDER_DEPTH,
+            player_config(opts, &opts.placer, PLACER_DEPTH)?,
+        ),
+        Role::Placer => (
+            PLACER_DEPTH,
+            player_config(opts, &opts.slider, SLIDER_DEPTH)?,
+        ),
+    };
+    let baseline = player_config(opts, &opts.baseline, depth)?;
+    let candidate = player_config(opts, &opts.candidate, depth)?;
+    let (d0, d1) = opts.delta;
+    let test = Sprt::new(d0, d1)
+        .with_errors(opts.alpha, opts.beta)
+        .with_max_pairs(Some(opts.games.unwrap_or(1000)))
+        .with_threads(opts.threads)
+        .with_seed(opts.seed)
+        .with_rules(opts.rules.clone());
+    println!(
+        "seed {}: {candidate} against {baseline} as the {:?}, opponent {opponent}",
+        opts.seed, opts.role
+    );
+    let (outcome, _) = test.run(opts.role, &baseline, &candidate, &opponent, |s| {
+        println!(
+            "pairs {:>6}  diff {:>9.2} +- {:<9.2}  llr {:>7.3}  [{:.3}, {:.3}]",
+            s.pairs, s.mean_diff, s.std_dev, s.llr, s.lower, s.upper
+        );
+    });
+    match outcome {
+        SprtOutcome::Accepted => println!("accepted: the candidate is better"),
+        SprtOutcome::Rejected => println!("rejected: the candidate isn't better"),
+        SprtOutcome::Inconclusive => {
+            println!("inconclusive: ran out of games, or both configs played the same games")
+        }
+    }
+    Ok(())
+}
+
+fn perft(opts: &Options, s: &Option<State>) -> Result<(), String> {
+    let s = match s {
+        Some(s) => s.clone(),
+        None => State::start(opts.rules.clone()),
+    };
+    let depth = opts.depth.unwrap_or(4);
+    if depth < 0 {
+        return Err(format!("invalid depth {depth}"));
+    }
+    let counts = ai::perft(&s, depth as u32).map_err(|d| format!("divergence: {d}"))?;
+    println!(
+        "{:>4} {:>14} {:>12} {:>12}",
+        "ply", "paths", "positions", "canonical"
+    );
+    for (ply, c) in counts.iter().enumerate() {
+        println!(
+            "{ply:>4} {:>14} {:>12} {:>12}",
+            c.paths, c.positions, c.canonical
+        );
+    }
+    Ok(())
+}
+
+fn solve(opts: &Options) -> Result<(), String> {
+    let s = solution(opts)?;
+    let v = s.start();
+    println!("{}x{}: {} positions", s.rows(), s.cols(), s.len());
+    println!("turns    {}", v.turns);
+    println!("max tile {}", v.max_tile);
+    println!("score    {}", v.score);
+    Ok(())
+}
+
+fn exit_on_error(result: Result<(), String>) {
+    if let Err(e) = result {
+        eprintln!("{e}");
+        process::exit(2);
+    }
+}
 
 fn main() {
-    let slider = Box::new(Ai::new(13));
-    let placer = Box::new(Random);
-    let mut g = Game::new(slider, placer);
-    g.play();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let opts = match parse_args(&args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    match &opts.command {
+        Command::Play | Command::SelfPlay => play(&opts),
+        Command::Analyze(s) => exit_on_error(analyze(&opts, s)),
+        Command::Arena => exit_on_error(arena(&opts)),
+        Command::Sprt => exit_on_error(sprt(&opts)),
+        Command::Perft(s) => exit_on_error(perft(&opts, s)),
+        Command::Solve => exit_on_error(solve(&opts)),
+        Command::Engine => {
+            let depth = opts.depth.unwrap_or(SLIDER_DEPTH);
+            let engine_opts = opts.clone();
+            let mut server = Server::new(depth)
+                .with_rules(opts.rules.clone())
+                .with_ai(move || make_ai(&engine_opts, None, SLIDER_DEPTH, engine_opts.seed));
+            if let Err(e) = server.run(io::stdin().lock(), io::stdout().lock()) {
+                eprintln!("engine I/O failed: {e}");
+                process::exit(1);
+            }
+        }
+    }
 }
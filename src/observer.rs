@@ -0,0 +1,192 @@
+use crate::state::{self, Move, Role, State};
+use crate::EngineInfo;
+use std::io::Write;
+
+// Receives everything that happens in a Game. All methods default to doing
+// nothing, so observers only implement what they care about.
+pub trait GameObserver {
+    fn on_game_start(&mut self, _s: &State) {}
+
+    // s is the state after m
+    fn on_move(&mut self, _m: &Move, _s: &State) {}
+
+    // a searching player reported on the move it's about to play
+    fn on_engine_info(&mut self, _role: Role, _info: &EngineInfo) {}
+
+    fn on_game_end(&mut self, _s: &State) {}
+}
+
+// Ignores everything
+pub struct Silent;
+
+impl GameObserver for Silent {}
+
+// Prints the grid before every slide, engine info and the final result
+pub struct PrettyPrinter {
+    every_move: bool,
+}
+
+impl PrettyPrinter {
+    pub fn new() -> PrettyPrinter {
+        PrettyPrinter { every_move: true }
+    }
+
+    // only the final result, for when something else draws the game
+    pub fn end_only() -> PrettyPrinter {
+        PrettyPrinter { every_move: false }
+    }
+}
+
+impl Default for PrettyPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GameObserver for PrettyPrinter {
+    fn on_move(&mut self, _m: &Move, s: &State) {
+        if self.every_move && s.next_to_move() == Role::Slider && !s.terminal() {
+            state::print_grid(s.grid());
+        }
+    }
+
+    fn on_engine_info(&mut self, role: Role, info: &EngineInfo) {
+        if self.every_move {
+            let pv: Vec<String> = info.pv.iter().map(|m| m.to_string()).collect();
+            println!(
+                "{role:?}: depth {} value {} pv {}",
+                info.depth,
+                info.value,
+                pv.join(" ")
+            );
+        }
+    }
+
+    fn on_game_end(&mut self, s: &State) {
+        let end = if s.won() {
+            "Target reached"
+        } else {
+            "Game over"
+        };
+        println!("{end}! Score = {}, Final state =", s.score());
+        state::print_grid(s.grid());
+    }
+}
+
+// Writes the game in the record format: "# key: value" header lines, then
+// one move per line in Move's notation, then the final score. A failed
+// write is reported once on stderr and ends the record.
+pub struct RecordWriter<W: Write> {
+    out: W,
+    failed: bool,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(out: W, header: &[(&str, String)]) -> RecordWriter<W> {
+        let mut writer = RecordWriter { out, failed: false };
+        for (key, value) in header {
+            writer.write_line(&format!("# {key}: {value}"));
+        }
+        writer
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = writeln!(self.out, "{line}") {
+            eprintln!("failed to write record: {e}");
+            self.failed = true;
+        }
+    }
+}
+
+impl<W: Write> GameObserver for RecordWriter<W> {
+    fn on_move(&mut self, m: &Move, _s: &State) {
+        self.write_line(&m.to_string());
+    }
+
+    fn on_game_end(&mut self, s: &State) {
+        self.write_line(&format!("# score: {}", s.score()));
+        if !self.failed {
+            if let Err(e) = self.out.flush() {
+                eprintln!("failed to write record: {e}");
+                self.failed = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+    use crate::Game;
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct Counts {
+        starts: usize,
+        moves: usize,
+        ends: usize,
+    }
+
+    struct Counter(Rc<RefCell<Counts>>);
+
+    impl GameObserver for Counter {
+        fn on_game_start(&mut self, _s: &State) {
+            self.0.borrow_mut().starts += 1;
+        }
+
+        fn on_move(&mut self, _m: &Move, _s: &State) {
+            self.0.borrow_mut().moves += 1;
+        }
+
+        fn on_game_end(&mut self, _s: &State) {
+            self.0.borrow_mut().ends += 1;
+        }
+    }
+
+    #[test]
+    fn dispatch_and_record() {
+        let counts = Rc::new(RefCell::new(Counts::default()));
+        let buf = SharedBuf::default();
+        let mut g = Game::new(Box::new(Random::new(1)), Box::new(Random::new(2)));
+        g.add_observer(Box::new(Silent));
+        g.add_observer(Box::new(Counter(counts.clone())));
+        g.add_observer(Box::new(RecordWriter::new(
+            buf.clone(),
+            &[("seed", "1".to_string())],
+        )));
+        g.play();
+
+        let counts = counts.borrow();
+        assert_eq!((counts.starts, counts.ends), (1, 1));
+        assert_eq!(counts.moves, g.moves().len());
+
+        let record = String::from_utf8(buf.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = record.lines().collect();
+        assert_eq!(lines[0], "# seed: 1");
+        assert_eq!(lines[1], g.moves()[0].to_string());
+        assert_eq!(lines.len(), g.moves().len() + 2);
+        assert_eq!(
+            *lines.last().unwrap(),
+            format!("# score: {}", g.state().score())
+        );
+    }
+}
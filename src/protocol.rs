@@ -0,0 +1,325 @@
+// A line-based protocol for driving an engine over stdin/stdout, in the
+// spirit of UCI. Commands from the controller, one per line:
+//
+//   a2048                     handshake: the engine answers with its id
+//                             lines and a2048ok
+//   isready                   the engine answers readyok
+//   newgame                   forget everything searched so far
+//   rules <name>              rule variant for the positions that follow,
+//                             the server's own (see Server::with_rules,
+//                             standard by default) until told otherwise,
+//                             or e.g.
+//                             standard:2,4,8 for other spawns, fibonacci,
+//                             threes, standard+walls:2, standard+drops:2,
+//                             standard+slides:DLR,UDL or
+//                             standard+target:2048 (see rules::by_name)
+//   position startpos [moves <m>...]
+//   position <grid> <s|p> [<score> [<phase>] [<placed>]] [moves <m>...]
+//                             set the position, in State's notation, and
+//                             play the given moves from it
+//   go [depth <n>] [movetime <ms>]
+//                             search the position. The engine prints an
+//                             info line per completed depth, then bestmove
+//   quit
+//
+// and from the engine:
+//
+//   id name <name>
+//   id version <version>
+//   a2048ok
+//   readyok
+//   info depth <n> score <value> pv <m>...
+//   bestmove <m>              or bestmove none if there's no legal move,
+//                             e.g. once the target is made
+//   error <message>           the command was ignored
+//
+// Moves are written in Move's notation (U, D, L, R or <val>@<x>,<y>), and
+// scores are from the point of view of the side to move. Searches run
+// synchronously, so there's no stop command.
+
+use crate::ai::{self, Ai};
+use crate::rules::{self, GameRules};
+use crate::state::{self, State};
+use crate::EngineInfo;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const HANDSHAKE: &str = "a2048";
+
+pub struct Server {
+    depth: i32,
+    // makes the Ai for each new game
+    new_ai: Box<dyn Fn() -> Ai>,
+    ai: Ai,
+    rules: String,
+    // the board startpos and the rules command are for
+    size: (usize, usize),
+    state: State,
+}
+
+impl Server {
+    // depth is used by go commands that don't give one
+    pub fn new(depth: i32) -> Server {
+        Server {
+            depth,
+            new_ai: Box::new(move || Ai::new(depth)),
+            ai: Ai::new(depth),
+            rules: "standard".to_string(),
+            size: (state::ROWS, state::COLS),
+            state: State::default(),
+        }
+    }
+
+    // The rules until a rules command, on their board
+    pub fn with_rules(mut self, rules: Arc<dyn GameRules>) -> Server {
+        self.rules = rules.name();
+        self.size = (rules.rows(), rules.cols());
+        self.state = State::start(rules);
+        self
+    }
+
+    // For an Ai set up other than Ai::new(depth), e.g. with a difficulty or
+    // a search cache. new_ai is called again for every newgame.
+    pub fn with_ai(mut self, new_ai: impl Fn() -> Ai + 'static) -> Server {
+        self.ai = new_ai();
+        self.new_ai = Box::new(new_ai);
+        self
+    }
+
+    // Serves commands until quit or the end of input
+    pub fn run(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        for line in input.lines() {
+            if !self.handle(&line?, &mut out)? {
+                break;
+            }
+            out.flush()?;
+        }
+        out.flush()
+    }
+
+    // Returns false on quit
+    pub fn handle(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let args: Vec<&str> = words.collect();
+        let result = match command {
+            HANDSHAKE => {
+                writeln!(out, "id name {}", env!("CARGO_PKG_NAME"))?;
+                writeln!(out, "id version {}", env!("CARGO_PKG_VERSION"))?;
+                writeln!(out, "{HANDSHAKE}ok")?;
+                Ok(())
+            }
+            "isready" => {
+                writeln!(out, "readyok")?;
+                Ok(())
+            }
+            "newgame" => {
+                self.ai = (self.new_ai)();
+                self.state = State::start(self.start_rules());
+                Ok(())
+            }
+            "rules" => match args[..] {
+                [name] if rules::by_name(name, self.size.0, self.size.1).is_some() => {
+                    self.rules = name.to_string();
+                    Ok(())
+                }
+                _ => Err(format!("unsupported rules {}", args.join(" "))),
+            },
+            "position" => self.position(&args),
+            "go" => self.go(&args, out)?,
+            "quit" => return Ok(false),
+            _ => Err(format!("unknown command {command}")),
+        };
+        if let Err(e) = result {
+            writeln!(out, "error {e}")?;
+        }
+        Ok(true)
+    }
+
+    fn position(&mut self, args: &[&str]) -> Result<(), String> {
+        let (position, moves) = match args.iter().position(|&a| a == "moves") {
+            Some(i) => (&args[..i], &args[i + 1..]),
+            None => (args, &[][..]),
+        };
+        let mut s = match position {
+            ["startpos"] => State::start(self.start_rules()),
+            _ => {
+                let notation = position.join(" ");
+                State::from_notation_with(&notation, &self.rules)
+                    .map_err(|_| format!("invalid position {notation}"))?
+            }
+        };
+        if s.grid().rows() * s.grid().cols() > ai::MAX_CELLS {
+            return Err(format!("board larger than {} cells", ai::MAX_CELLS));
+        }
+        for notation in moves {
+            let m = notation
+                .parse()
+                .map_err(|_| format!("invalid move {notation}"))?;
+            s = state::next_state(&s, m).map_err(|_| format!("illegal move {notation}"))?;
+        }
+        self.state = s;
+        Ok(())
+    }
+
+    fn start_rules(&self) -> Arc<dyn GameRules> {
+        rules::by_name(&self.rules, self.size.0, self.size.1)
+            .expect("rules are checked by the rules command")
+    }
+
+    fn go(&mut self, args: &[&str], out: &mut impl Write) -> io::Result<Result<(), String>> {
+        let mut depth = None;
+        let mut limit = None;
+        let mut args = args.iter();
+        while let Some(&name) = args.next() {
+            let value = args.next().and_then(|v| v.parse::<u64>().ok());
+            match (name, value) {
+                ("depth", Some(d)) if d > 0 => depth = Some(d.min(i32::MAX as u64) as i32),
+                ("movetime", Some(ms)) => limit = Some(Duration::from_millis(ms)),
+                _ => return Ok(Err(format!("invalid go argument {name}"))),
+            }
+        }
+        // a time limit alone searches as deep as the time allows
+        let depth = match (depth, limit) {
+            (Some(d), _) => d,
+            (None, Some(_)) => i32::MAX,
+            (None, None) => self.depth,
+        };
+
+        let mut written = Ok(());
+        let result = self.ai.search(&self.state, depth, limit, |info| {
+            if written.is_ok() {
+                written = writeln!(out, "{}", info_line(info));
+            }
+        });
+        written?;
+        match result {
+            Ok(Some(info)) => writeln!(out, "bestmove {}", info.pv[0])?,
+            Ok(None) => writeln!(out, "bestmove none")?,
+            Err(_) => return Ok(Err("unreachable position".to_string())),
+        }
+        Ok(Ok(()))
+    }
+}
+
+pub fn info_line(info: &EngineInfo) -> String {
+    let pv: Vec<String> = info.pv.iter().map(|m| m.to_string()).collect();
+    format!(
+        "info depth {} score {} pv {}",
+        info.depth,
+        info.value,
+        pv.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn session(commands: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        Server::new(2).run(commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn handshake() {
+        let lines = session("a2048\nisready\nquit\nisready\n");
+        assert_eq!(lines[0], "id name adversarial-2048");
+        assert_eq!(lines[2..], ["a2048ok", "readyok"]);
+    }
+
+    #[test]
+    fn go() {
+        let lines = session("position startpos moves 2@0,0\ngo depth 3\n");
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("info depth 1 score "));
+        assert!(lines[2].starts_with("info depth 3 score "));
+        let best = lines[3].strip_prefix("bestmove ").unwrap();
+        // the pv of the last iteration starts with the best move
+        assert!(lines[2].contains(&format!(" pv {best}")));
+        assert!(["D", "R"].contains(&best));
+
+        // a Slider that can't move
+        let dead = "2,4,2,4/4,2,4,2/2,4,2,4/4,2,4,2 s";
+        assert_eq!(
+            session(&format!("position {dead}\ngo\n")),
+            ["bestmove none"]
+        );
+    }
+
+    #[test]
+    fn rules() {
+        let lines = session(
+            "rules standard:4\nposition startpos moves 2@0,0\n\
+             position 2,0/0,0 s\nposition startpos moves 4@0,0\ngo depth 2\n",
+        );
+        assert_eq!(lines[0], "error illegal move 2@0,0");
+        assert_eq!(lines[1], "error invalid position 2,0/0,0 s");
+        assert!(["bestmove D", "bestmove R"].contains(&lines[lines.len() - 1].as_str()));
+    }
+
+    #[test]
+    fn configured_server() {
+        let made = Rc::new(Cell::new(0));
+        let counter = made.clone();
+        let mut server = Server::new(2)
+            .with_rules(rules::by_name("standard:3", 3, 3).unwrap())
+            .with_ai(move || {
+                counter.set(counter.get() + 1);
+                Ai::new(2)
+            });
+        let mut out = Vec::new();
+        let commands = "position startpos moves 3@0,0\nnewgame\ngo depth 1\n";
+        server.run(commands.as_bytes(), &mut out).unwrap();
+        let lines = String::from_utf8(out).unwrap();
+        // the empty 3x3 board, where only 3s are placed
+        assert!(lines.contains("bestmove 3@"), "{lines}");
+        assert_eq!(made.get(), 2);
+
+        // a cell beyond a 2x2 board, so the rules are checked on 3x3
+        let commands = "rules standard+blocked:2,2\nposition startpos\n";
+        server.run(commands.as_bytes(), &mut Vec::new()).unwrap();
+        assert_eq!(server.state.grid()[2][2], state::WALL);
+    }
+
+    #[test]
+    fn errors() {
+        let lines = session(
+            "frobnicate\nrules hexagonal\nposition startpos moves U\n\
+             position 3,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 p\ngo depth x\nrules standard\n",
+        );
+        assert_eq!(
+            lines,
+            [
+                "error unknown command frobnicate",
+                "error unsupported rules hexagonal",
+                "error illegal move U",
+                "error invalid position 3,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 p",
+                "error invalid go argument depth",
+            ]
+        );
+
+        // the last position stays when a board is too large to search
+        let row = ["0"; 7].join(",");
+        let big = vec![row; 7].join("/");
+        let lines = session(&format!(
+            "position startpos moves 2@0,0
+position {big} p
+go depth 1
+"
+        ));
+        assert_eq!(lines[0], "error board larger than 36 cells");
+        assert!(["bestmove D", "bestmove R"].contains(&lines[2].as_str()));
+    }
+}
@@ -1,30 +1,118 @@
 use crate::state::{self, Move, Role, State, SLIDER_MOVES};
 use crate::Player;
-use itertools::iproduct;
+use rand::distributions::{Distribution, WeightedIndex};
 use rand::prelude::IteratorRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
-pub struct Random;
+// spawn values with their relative weights, as in the original 2048
+pub const CLASSIC_SPAWNS: [(i32, f64); 2] = [(2, 0.9), (4, 0.1)];
+
+// Plays a uniformly random legal slide, or places a tile on a uniformly
+// random empty cell with its value drawn from the spawn distribution. The
+// same seed always gives the same moves.
+pub struct Random {
+    seed: u64,
+    rng: StdRng,
+    // None for the smallest spawn of the game's rules
+    spawns: Option<(Vec<i32>, WeightedIndex<f64>)>,
+}
+
+impl Random {
+    // only places the smallest tile the rules allow, 2s under the standard
+    // rules
+    pub fn new(seed: u64) -> Random {
+        Random {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            spawns: None,
+        }
+    }
+
+    pub fn from_entropy() -> Random {
+        Random::new(rand::random())
+    }
+
+    // Panics unless every value is positive and the weights are
+    // non-negative with a positive sum. Values the game's rules don't spawn
+    // make illegal moves.
+    pub fn with_spawns(mut self, spawns: &[(i32, f64)]) -> Random {
+        assert!(
+            spawns.iter().all(|&(val, _)| val > 0),
+            "Invalid spawn value"
+        );
+        let vals = spawns.iter().map(|&(val, _)| val).collect();
+        let dist =
+            WeightedIndex::new(spawns.iter().map(|&(_, w)| w)).expect("Invalid spawn weights");
+        self.spawns = Some((vals, dist));
+        self
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
 
 impl Player for Random {
     fn pick_move(&mut self, s: &State) -> Move {
-        let mut rng = rand::thread_rng();
         if s.next_to_move() == Role::Slider {
             SLIDER_MOVES
                 .into_iter()
                 .filter(|&m| state::next_state(s, m).is_ok())
-                .choose(&mut rng)
+                .choose(&mut self.rng)
                 .unwrap()
         } else {
             let grid = s.grid();
-            iproduct!(0..4, 0..4, 0..1)
-                .filter(|&(i, j, _)| grid[i][j] == 0)
-                .map(|(i, j, k)| Move::Place {
-                    x: i,
-                    y: j,
-                    val: 2 * k + 2,
-                })
-                .choose(&mut rng)
-                .unwrap()
+            let (x, y) = grid
+                .coords()
+                .filter(|&(i, j)| grid[i][j] == 0)
+                .choose(&mut self.rng)
+                .unwrap();
+            let val = match &self.spawns {
+                Some((vals, dist)) => vals[dist.sample(&mut self.rng)],
+                None => s.rules().spawns()[0],
+            };
+            Move::Place { x, y, val }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play_moves(player: &mut Random, n: usize) -> Vec<Move> {
+        let mut s = State::default();
+        let mut moves = Vec::new();
+        while moves.len() < n && !s.terminal() {
+            let m = player.pick_move(&s);
+            s = state::next_state(&s, m).unwrap();
+            moves.push(m);
         }
+        moves
+    }
+
+    #[test]
+    fn same_seed_same_moves() {
+        let a = play_moves(&mut Random::new(7).with_spawns(&CLASSIC_SPAWNS), 200);
+        let b = play_moves(&mut Random::new(7).with_spawns(&CLASSIC_SPAWNS), 200);
+        assert_eq!(a, b);
+        assert_ne!(
+            a,
+            play_moves(&mut Random::new(8).with_spawns(&CLASSIC_SPAWNS), 200)
+        );
+    }
+
+    #[test]
+    fn spawn_distribution() {
+        let moves = play_moves(&mut Random::new(1), 200);
+        assert!(moves
+            .iter()
+            .all(|m| !matches!(m, Move::Place { val, .. } if *val != 2)));
+
+        let moves = play_moves(&mut Random::new(1).with_spawns(&[(4, 1.0), (2, 0.0)]), 200);
+        assert!(moves
+            .iter()
+            .all(|m| !matches!(m, Move::Place { val, .. } if *val != 4)));
     }
 }
@@ -0,0 +1,765 @@
+// What a game variant is made of. The mechanics (tiles sliding towards an
+// edge, the Placer filling an empty cell) are the same for every variant and
+// live in state.rs; the rules decide the board, which tiles may be placed,
+// which tiles merge into what, what a merge scores and when the game ends,
+// with a win for the Slider if they set a target tile.
+// Walls (state::WALL) block cells for good: tiles slide up to them but never
+// through or into them.
+//
+// States carry their rules, so players and the search pick them up from the
+// positions they're given.
+
+use crate::state::{self, Direction, Move, Role, State, DIRECTIONS, SLIDER_MOVES};
+use std::fmt;
+use std::sync::Arc;
+
+// tiles above this aren't considered, so values stay well inside an i32
+pub const MAX_TILE: i32 = 1 << 30;
+
+// the most tile values rules can have, so that the search can code each of
+// them and a wall in a byte
+pub const MAX_TILE_VALUES: usize = u8::MAX as usize - 1;
+
+pub trait GameRules: fmt::Debug + Send + Sync {
+    // identifies the variant, as in the engine protocol's rules command. Two
+    // rules with the same name and board size must behave the same.
+    fn name(&self) -> String;
+
+    fn rows(&self) -> usize;
+
+    fn cols(&self) -> usize;
+
+    // tile values the Placer may place, smallest first
+    fn spawns(&self) -> &[i32];
+
+    // What a tile merges into when another slides into it, if they merge.
+    // a is the tile nearer the edge the tiles slide towards.
+    fn merge(&self, a: i32, b: i32) -> Option<i32>;
+
+    // points for a merge that made the tile merged
+    fn merge_score(&self, merged: i32) -> i32 {
+        merged
+    }
+
+    // Whether a slide moves each line at most one cell, as in Threes: the
+    // tile nearest the edge that can move into an empty cell or merge does
+    // so, taking every tile behind it along. Otherwise tiles slide as far as
+    // they go.
+    fn one_step(&self) -> bool {
+        false
+    }
+
+    // How many turns the Slider's directions take to repeat. The Slider's
+    // n-th slide (counting from 0) is in phase n % phases().
+    fn phases(&self) -> usize {
+        1
+    }
+
+    // the directions the Slider may slide in, in phase (see phases)
+    fn directions(&self, _phase: usize) -> &[Direction] {
+        &DIRECTIONS
+    }
+
+    // cells that hold a wall from the start, row-major
+    fn blocked(&self) -> &[(usize, usize)] {
+        &[]
+    }
+
+    // how many walls the Placer may place instead of a tile over a game
+    fn walls(&self) -> usize {
+        0
+    }
+
+    // How many tiles the Placer places per turn, one ply each. The turn ends
+    // early when the board fills up.
+    fn drops(&self) -> usize {
+        1
+    }
+
+    // The tile the Slider wins by making, if the rules have a win condition.
+    // The game ends as soon as it's on the board, see State::won.
+    fn target(&self) -> Option<i32> {
+        None
+    }
+
+    // When the Slider has lost, by default when it has no legal slide
+    fn terminal(&self, s: &State) -> bool {
+        s.next_to_move() == Role::Slider && state::dead_grid(s.grid(), self)
+    }
+
+    // every placement, legal or not: each spawn value in turn, row-major
+    fn placer_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for &val in self.spawns() {
+            for x in 0..self.rows() {
+                for y in 0..self.cols() {
+                    moves.push(Move::Place { x, y, val });
+                }
+            }
+        }
+        moves
+    }
+
+    // every move of role, legal or not
+    fn moves(&self, role: Role) -> Vec<Move> {
+        match role {
+            Role::Slider => SLIDER_MOVES.to_vec(),
+            Role::Placer => self.placer_moves(),
+        }
+    }
+}
+
+// Every tile that can appear: the spawns and whatever merging them makes, up
+// to MAX_TILE, smallest first
+pub fn tile_values(rules: &dyn GameRules) -> Vec<i32> {
+    let mut values = rules.spawns().to_vec();
+    let mut i = 0;
+    // every pair is tried once, as each new value meets the earlier ones
+    while i < values.len() {
+        for j in 0..=i {
+            let (a, b) = (values[i], values[j]);
+            for merged in [rules.merge(a, b), rules.merge(b, a)].into_iter().flatten() {
+                if merged <= MAX_TILE && !values.contains(&merged) {
+                    values.push(merged);
+                }
+            }
+        }
+        i += 1;
+    }
+    values.sort_unstable();
+    values
+}
+
+// The classic rules: 2s and 4s are placed, equal tiles merge into their sum,
+// which is also what the merge scores, and the game ends when the Slider is
+// stuck. The placed tiles can be changed, which shows in the name, e.g.
+// standard:2,4,8.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Standard {
+    rows: usize,
+    cols: usize,
+    spawns: Vec<i32>,
+}
+
+const STANDARD_SPAWNS: [i32; 2] = [2, 4];
+
+impl Standard {
+    pub fn new(rows: usize, cols: usize) -> Standard {
+        Standard::with_spawns(rows, cols, &STANDARD_SPAWNS)
+    }
+
+    // Panics unless spawns is a non-empty set of positive values, in any
+    // order
+    pub fn with_spawns(rows: usize, cols: usize, spawns: &[i32]) -> Standard {
+        assert!(rows > 0 && cols > 0, "Empty board");
+        assert!(
+            !spawns.is_empty() && spawns.iter().all(|&val| val > 0 && val <= MAX_TILE),
+            "Invalid spawns"
+        );
+        let mut spawns = spawns.to_vec();
+        spawns.sort_unstable();
+        spawns.dedup();
+        Standard { rows, cols, spawns }
+    }
+}
+
+impl Default for Standard {
+    fn default() -> Self {
+        Standard::new(state::ROWS, state::COLS)
+    }
+}
+
+impl GameRules for Standard {
+    fn name(&self) -> String {
+        if self.spawns == STANDARD_SPAWNS {
+            "standard".to_string()
+        } else {
+            let spawns: Vec<String> = self.spawns.iter().map(|val| val.to_string()).collect();
+            format!("standard:{}", spawns.join(","))
+        }
+    }
+
+    fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn spawns(&self) -> &[i32] {
+        &self.spawns
+    }
+
+    fn merge(&self, a: i32, b: i32) -> Option<i32> {
+        (a == b).then(|| a.checked_add(b)).flatten()
+    }
+}
+
+// 1s and 2s are placed, and tiles that are neighbours in the Fibonacci
+// sequence (1 and 1, 1 and 2, 2 and 3, 3 and 5...) merge into the next one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fibonacci {
+    rows: usize,
+    cols: usize,
+}
+
+impl Fibonacci {
+    pub fn new(rows: usize, cols: usize) -> Fibonacci {
+        assert!(rows > 0 && cols > 0, "Empty board");
+        Fibonacci { rows, cols }
+    }
+}
+
+impl GameRules for Fibonacci {
+    fn name(&self) -> String {
+        "fibonacci".to_string()
+    }
+
+    fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn spawns(&self) -> &[i32] {
+        &[1, 2]
+    }
+
+    fn merge(&self, a: i32, b: i32) -> Option<i32> {
+        let (lo, hi) = (a.min(b), a.max(b));
+        let (mut x, mut y) = (1, 1);
+        while x < lo || (x == lo && y < hi) {
+            (x, y) = (y, x.checked_add(y)?);
+        }
+        ((x, y) == (lo, hi)).then(|| lo.checked_add(hi)).flatten()
+    }
+}
+
+// Like Threes: 1s, 2s and 3s are placed, a 1 and a 2 merge into a 3, equal
+// tiles from 3 up merge into their sum, and lines move one cell per slide
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threes {
+    rows: usize,
+    cols: usize,
+}
+
+impl Threes {
+    pub fn new(rows: usize, cols: usize) -> Threes {
+        assert!(rows > 0 && cols > 0, "Empty board");
+        Threes { rows, cols }
+    }
+}
+
+impl GameRules for Threes {
+    fn name(&self) -> String {
+        "threes".to_string()
+    }
+
+    fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn spawns(&self) -> &[i32] {
+        &[1, 2, 3]
+    }
+
+    fn merge(&self, a: i32, b: i32) -> Option<i32> {
+        match (a, b) {
+            (1, 2) | (2, 1) => Some(3),
+            _ if a == b && a >= 3 => a.checked_add(b),
+            _ => None,
+        }
+    }
+
+    fn one_step(&self) -> bool {
+        true
+    }
+}
+
+// Other rules with modifiers on top, each showing in the name:
+//   blocked cells that start out walled       +blocked:0,0;3,3
+//   walls the Placer may place over the game  +walls:2
+//   tiles the Placer places per turn          +drops:2
+//   a schedule of the Slider's directions     +slides:UDL or +slides:DLR,UDL
+//   a tile the Slider wins by making          +target:2048
+// in that order, e.g. standard+blocked:1,1+walls:2.
+#[derive(Debug, Clone)]
+pub struct Variant {
+    base: Arc<dyn GameRules>,
+    blocked: Vec<(usize, usize)>,
+    walls: usize,
+    drops: usize,
+    // empty for every direction on every turn
+    schedule: Vec<Vec<Direction>>,
+    target: Option<i32>,
+}
+
+impl Variant {
+    // the base rules unchanged, until modifiers are added
+    pub fn new(base: Arc<dyn GameRules>) -> Variant {
+        Variant {
+            base,
+            blocked: Vec::new(),
+            walls: 0,
+            drops: 1,
+            schedule: Vec::new(),
+            target: None,
+        }
+    }
+
+    // Panics if a cell is off the board or nothing is left to play on
+    pub fn with_blocked(mut self, cells: &[(usize, usize)]) -> Variant {
+        let mut blocked = cells.to_vec();
+        blocked.sort_unstable();
+        blocked.dedup();
+        assert!(
+            blocked
+                .iter()
+                .all(|&(x, y)| x < self.rows() && y < self.cols()),
+            "Blocked cell off the board"
+        );
+        assert!(
+            blocked.len() < self.rows() * self.cols(),
+            "Every cell is blocked"
+        );
+        self.blocked = blocked;
+        self
+    }
+
+    pub fn with_walls(mut self, walls: usize) -> Variant {
+        self.walls = walls;
+        self
+    }
+
+    // Panics unless drops > 0
+    pub fn with_drops(mut self, drops: usize) -> Variant {
+        assert!(drops > 0, "No drops per turn");
+        self.drops = drops;
+        self
+    }
+
+    // The Slider's n-th slide (counting from 0) may only go in the directions
+    // of schedule[n % schedule.len()]. Panics on a turn without directions.
+    pub fn with_schedule(mut self, schedule: &[Vec<Direction>]) -> Variant {
+        self.schedule = schedule
+            .iter()
+            .map(|dirs| {
+                let dirs: Vec<Direction> = DIRECTIONS
+                    .into_iter()
+                    .filter(|d| dirs.contains(d))
+                    .collect();
+                assert!(!dirs.is_empty(), "Turn without directions");
+                dirs
+            })
+            .collect();
+        // every direction on every turn is no schedule at all
+        if self
+            .schedule
+            .iter()
+            .all(|dirs| dirs.len() == DIRECTIONS.len())
+        {
+            self.schedule.clear();
+        }
+        self
+    }
+
+    // Panics unless target is a tile of the base rules that only merges
+    // make, i.e. larger than every spawn
+    pub fn with_target(mut self, target: i32) -> Variant {
+        assert!(valid_target(&*self.base, target), "Invalid target");
+        self.target = Some(target);
+        self
+    }
+
+    // what's added to the base rules' name
+    fn suffix(&self) -> String {
+        let mut suffix = String::new();
+        if !self.blocked.is_empty() {
+            let cells: Vec<String> = self
+                .blocked
+                .iter()
+                .map(|(x, y)| format!("{x},{y}"))
+                .collect();
+            suffix += &format!("+blocked:{}", cells.join(";"));
+        }
+        if self.walls > 0 {
+            suffix += &format!("+walls:{}", self.walls);
+        }
+        if self.drops > 1 {
+            suffix += &format!("+drops:{}", self.drops);
+        }
+        if !self.schedule.is_empty() {
+            let turns: Vec<String> = self
+                .schedule
+                .iter()
+                .map(|dirs| dirs.iter().map(|&d| Move::Slide(d).to_string()).collect())
+                .collect();
+            suffix += &format!("+slides:{}", turns.join(","));
+        }
+        if let Some(target) = self.target {
+            suffix += &format!("+target:{target}");
+        }
+        suffix
+    }
+}
+
+impl GameRules for Variant {
+    fn name(&self) -> String {
+        self.base.name() + &self.suffix()
+    }
+
+    fn rows(&self) -> usize {
+        self.base.rows()
+    }
+
+    fn cols(&self) -> usize {
+        self.base.cols()
+    }
+
+    fn spawns(&self) -> &[i32] {
+        self.base.spawns()
+    }
+
+    fn merge(&self, a: i32, b: i32) -> Option<i32> {
+        self.base.merge(a, b)
+    }
+
+    fn merge_score(&self, merged: i32) -> i32 {
+        self.base.merge_score(merged)
+    }
+
+    fn one_step(&self) -> bool {
+        self.base.one_step()
+    }
+
+    fn blocked(&self) -> &[(usize, usize)] {
+        &self.blocked
+    }
+
+    fn walls(&self) -> usize {
+        self.walls
+    }
+
+    fn drops(&self) -> usize {
+        self.drops
+    }
+
+    fn phases(&self) -> usize {
+        self.schedule.len().max(1)
+    }
+
+    fn target(&self) -> Option<i32> {
+        self.target
+    }
+
+    fn directions(&self, phase: usize) -> &[Direction] {
+        match self.schedule.get(phase) {
+            Some(dirs) => dirs,
+            None => &DIRECTIONS,
+        }
+    }
+
+    // a Slider that can still slide may have none of this turn's directions
+    fn terminal(&self, s: &State) -> bool {
+        self.base.terminal(s)
+            || (!self.schedule.is_empty()
+                && s.next_to_move() == Role::Slider
+                && self
+                    .directions(s.phase())
+                    .iter()
+                    .all(|&d| state::next_state(s, Move::Slide(d)).is_err()))
+    }
+
+    // the base rules' placements, then a wall on each cell
+    fn placer_moves(&self) -> Vec<Move> {
+        let mut moves = self.base.placer_moves();
+        if self.walls > 0 {
+            for x in 0..self.rows() {
+                for y in 0..self.cols() {
+                    moves.push(Move::Place {
+                        x,
+                        y,
+                        val: state::WALL,
+                    });
+                }
+            }
+        }
+        moves
+    }
+}
+
+// The symmetries of the board (see state::Direction::transformed) that keep
+// the game the same: those mapping every turn's directions onto themselves
+pub fn symmetries(rules: &dyn GameRules) -> Vec<usize> {
+    let count = if rules.rows() == rules.cols() { 8 } else { 4 };
+    (0..count)
+        .filter(|&t| {
+            (0..rules.phases()).all(|phase| {
+                let dirs = rules.directions(phase);
+                dirs.iter().all(|d| dirs.contains(&d.transformed(t)))
+            })
+        })
+        .collect()
+}
+
+// The rules called name on a rows x cols board, None for unknown names.
+// Names are base rules, then optionally the modifiers of Variant.
+pub fn by_name(name: &str, rows: usize, cols: usize) -> Option<Arc<dyn GameRules>> {
+    let (base, modifiers) = match name.split_once('+') {
+        Some((base, modifiers)) => (base, Some(modifiers)),
+        None => (name, None),
+    };
+    let base = base_by_name(base, rows, cols)?;
+    let modifiers = match modifiers {
+        Some(modifiers) => modifiers,
+        None => return Some(base),
+    };
+    let mut rules = Variant::new(base);
+    for modifier in modifiers.split('+') {
+        rules = match modifier.split_once(':')? {
+            ("blocked", cells) => {
+                let cells = parse_cells(cells, rows, cols)?;
+                if cells.len() >= rows * cols {
+                    return None;
+                }
+                rules.with_blocked(&cells)
+            }
+            ("walls", n) => rules.with_walls(n.parse().ok().filter(|&n| n > 0)?),
+            ("drops", k) => rules.with_drops(k.parse().ok().filter(|&k| k > 1)?),
+            ("slides", turns) => rules.with_schedule(&parse_schedule(turns)?),
+            ("target", t) => {
+                let target = t.parse().ok()?;
+                if !valid_target(&*rules.base, target) {
+                    return None;
+                }
+                rules.with_target(target)
+            }
+            _ => return None,
+        };
+    }
+    // so each variant has one name
+    (rules.suffix() == format!("+{modifiers}")).then_some(Arc::new(rules))
+}
+
+fn valid_target(rules: &dyn GameRules, target: i32) -> bool {
+    target > *rules.spawns().last().unwrap() && tile_values(rules).contains(&target)
+}
+
+fn base_by_name(name: &str, rows: usize, cols: usize) -> Option<Arc<dyn GameRules>> {
+    match name.split_once(':') {
+        None if name == "standard" => Some(Arc::new(Standard::new(rows, cols))),
+        None if name == "fibonacci" => Some(Arc::new(Fibonacci::new(rows, cols))),
+        None if name == "threes" => Some(Arc::new(Threes::new(rows, cols))),
+        Some(("standard", spawns)) => {
+            let spawns = parse_spawns(spawns)?;
+            let rules = Standard::with_spawns(rows, cols, &spawns);
+            (tile_values(&rules).len() <= MAX_TILE_VALUES).then_some(Arc::new(rules))
+        }
+        _ => None,
+    }
+}
+
+// "0,0;3,3": cells on the board, in row-major order
+fn parse_cells(list: &str, rows: usize, cols: usize) -> Option<Vec<(usize, usize)>> {
+    let cells = list
+        .split(';')
+        .map(|cell| {
+            let (x, y) = cell.split_once(',')?;
+            let (x, y) = (x.parse().ok()?, y.parse().ok()?);
+            (x < rows && y < cols).then_some((x, y))
+        })
+        .collect::<Option<Vec<(usize, usize)>>>()?;
+    cells.windows(2).all(|w| w[0] < w[1]).then_some(cells)
+}
+
+// "UDL,DLR": each turn's directions, as in Move's notation
+fn parse_schedule(list: &str) -> Option<Vec<Vec<Direction>>> {
+    list.split(',')
+        .map(|turn| {
+            turn.chars()
+                .map(|c| match c.to_string().parse() {
+                    Ok(Move::Slide(d)) => Some(d),
+                    _ => None,
+                })
+                .collect::<Option<Vec<Direction>>>()
+                .filter(|dirs| !dirs.is_empty())
+        })
+        .collect()
+}
+
+// "2,4,8": distinct positive values, smallest first, so each set of spawns
+// has one name
+fn parse_spawns(list: &str) -> Option<Vec<i32>> {
+    let spawns = list
+        .split(',')
+        .map(|val| val.parse().ok().filter(|&val| val > 0 && val <= MAX_TILE))
+        .collect::<Option<Vec<i32>>>()?;
+    spawns.windows(2).all(|w| w[0] < w[1]).then_some(spawns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_tiles() {
+        let values = tile_values(&Standard::default());
+        assert_eq!(values.len(), 30);
+        assert_eq!((values[0], values[1], values[29]), (2, 4, MAX_TILE));
+        assert!(values.windows(2).all(|w| w[1] == 2 * w[0]));
+    }
+
+    #[test]
+    fn names() {
+        let rules = by_name("standard", 3, 5).unwrap();
+        assert_eq!(
+            (rules.name(), rules.rows(), rules.cols()),
+            ("standard".to_string(), 3, 5)
+        );
+        assert!(by_name("hexagonal", 4, 4).is_none());
+        assert_eq!(rules.placer_moves().len(), 30);
+    }
+
+    #[test]
+    fn fibonacci() {
+        let rules = by_name("fibonacci", 4, 4).unwrap();
+        assert_eq!(rules.spawns(), [1, 2]);
+        assert_eq!(&tile_values(&*rules)[..6], [1, 2, 3, 5, 8, 13]);
+        assert_eq!(rules.merge(1, 1), Some(2));
+        assert_eq!(rules.merge(8, 5), Some(13));
+        assert_eq!(rules.merge(2, 2), None);
+        assert_eq!(rules.merge(3, 8), None);
+        assert!(!rules.one_step());
+    }
+
+    #[test]
+    fn threes() {
+        let rules = by_name("threes", 4, 4).unwrap();
+        assert_eq!(&tile_values(&*rules)[..5], [1, 2, 3, 6, 12]);
+        assert_eq!(rules.merge(2, 1), Some(3));
+        assert_eq!(rules.merge(1, 1), None);
+        assert_eq!(rules.merge(6, 6), Some(12));
+        assert_eq!(rules.merge(3, 6), None);
+        assert!(rules.one_step());
+    }
+
+    #[test]
+    fn obstacles() {
+        let rules = by_name("threes+blocked:0,0;2,1+walls:3", 3, 3).unwrap();
+        assert_eq!(rules.name(), "threes+blocked:0,0;2,1+walls:3");
+        assert_eq!(rules.blocked(), [(0, 0), (2, 1)]);
+        assert_eq!(rules.walls(), 3);
+        assert!(rules.one_step());
+        assert_eq!(rules.placer_moves().len(), 4 * 9);
+        assert_eq!(by_name("standard+walls:1", 4, 4).unwrap().walls(), 1);
+        let rules = by_name("standard+walls:1+drops:2+slides:UD", 4, 4).unwrap();
+        assert_eq!((rules.walls(), rules.drops()), (1, 2));
+        assert_eq!(Standard::default().drops(), 1);
+
+        for name in [
+            "standard+",
+            "standard+walls:0",
+            "standard+walls:1+blocked:0,0",
+            "standard+drops:1",
+            "standard+drops:0",
+            "standard+drops:2+walls:1",
+            "standard+blocked:1,1;0,0",
+            "standard+blocked:4,0",
+            "standard+blocked:0,0;0,1;1,0;1,1",
+            "standard+doors:1",
+        ] {
+            assert!(by_name(name, 2, 2).is_none(), "{name}");
+        }
+    }
+
+    #[test]
+    fn schedules() {
+        let rules = by_name("standard+walls:1+slides:DLR,ULR", 4, 4).unwrap();
+        assert_eq!(rules.name(), "standard+walls:1+slides:DLR,ULR");
+        assert_eq!(rules.phases(), 2);
+        assert_eq!(
+            rules.directions(0),
+            [Direction::Down, Direction::Left, Direction::Right]
+        );
+        assert_eq!(rules.walls(), 1);
+        // only the flip that swaps left and right keeps both turns
+        assert_eq!(symmetries(&*rules), [0, 2]);
+        let rules = by_name("standard+slides:DLR,UDL", 4, 4).unwrap();
+        assert_eq!(symmetries(&*rules), [0]);
+        assert_eq!(symmetries(&Standard::default()), [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(symmetries(&Standard::new(2, 3)), [0, 1, 2, 3]);
+        // up/down or left/right each turn, so any transpose swaps the turns
+        let rules = by_name("standard+slides:UD,LR", 4, 4).unwrap();
+        assert_eq!(symmetries(&*rules), [0, 1, 2, 3]);
+        let rules = by_name("standard+slides:UDL", 4, 4).unwrap();
+        assert_eq!(symmetries(&*rules), [0, 1]);
+
+        for name in [
+            "standard+slides:",
+            "standard+slides:UDLR",
+            "standard+slides:DRL",
+            "standard+slides:UD,",
+            "standard+slides:UX",
+            "standard+slides:UDL+walls:1",
+        ] {
+            assert!(by_name(name, 4, 4).is_none(), "{name}");
+        }
+    }
+
+    #[test]
+    fn targets() {
+        let rules = by_name("standard+slides:UDL+target:2048", 4, 4).unwrap();
+        assert_eq!(rules.name(), "standard+slides:UDL+target:2048");
+        assert_eq!(rules.target(), Some(2048));
+        assert_eq!(
+            by_name("fibonacci+target:13", 4, 4).unwrap().target(),
+            Some(13)
+        );
+        assert_eq!(Standard::default().target(), None);
+
+        for name in [
+            // spawned, not made
+            "standard+target:4",
+            "standard+target:1000",
+            "fibonacci+target:4",
+            "standard+target:x",
+            "standard+target:2048+walls:1",
+        ] {
+            assert!(by_name(name, 4, 4).is_none(), "{name}");
+        }
+    }
+
+    #[test]
+    fn spawn_sets() {
+        let rules = by_name("standard:2,4,8", 4, 4).unwrap();
+        assert_eq!(rules.name(), "standard:2,4,8");
+        assert_eq!(rules.spawns(), [2, 4, 8]);
+        assert_eq!(rules.placer_moves().len(), 48);
+        assert_eq!(Standard::with_spawns(4, 4, &[4, 2, 4]).name(), "standard");
+
+        let rules = by_name("standard:3", 4, 4).unwrap();
+        let values = tile_values(&*rules);
+        assert_eq!(&values[..3], [3, 6, 12]);
+        assert!(values.iter().all(|&val| val % 3 == 0));
+
+        for name in [
+            "fibonacci:1",
+            "standard:",
+            "standard:0",
+            "standard:4,2",
+            "standard:2,2",
+            "standard:x",
+            // each odd spawn doubles into its own 30 values
+            "standard:1,3,5,7,9,11,13,15,17,19",
+        ] {
+            assert!(by_name(name, 4, 4).is_none(), "{name}");
+        }
+    }
+}
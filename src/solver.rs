@@ -0,0 +1,521 @@
+// Exhaustive solver for boards small enough to visit every position. Every
+// position reachable from the empty board is solved backwards from the dead
+// ends, separately for each objective, assuming both sides play perfectly:
+// the Slider maximizes and the Placer minimizes.
+//
+// Positions are stored by a canonical key, the smallest packing of the grid
+// over the symmetries that keep the rules, so symmetric positions share an
+// entry. Each tile is packed as its index among the rules' tile values, in
+// as few bits as they need. Scores are kept relative to the position, so
+// the same grid reached with different scores also shares one.
+//
+// Table file layout (little endian):
+//   magic "A2048SV\0", version: u32, rows: u8, cols: u8,
+//   rules name length: u8, rules name, entry count: u64
+//   per entry: key: u64, turns: u32, max_tile: i32, score: i32
+
+use crate::rules::{self, GameRules};
+use crate::state::{self, Move, Role, State};
+use crate::Player;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// The standard rules' tiles take 5 bits per cell, and those and one for the
+// side to move have to fit in a key
+pub const MAX_CELLS: usize = 12;
+
+const MAGIC: &[u8; 8] = b"A2048SV\0";
+const VERSION: u32 = 2;
+
+// The outcome of perfect play from a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Value {
+    // slides the Slider still gets to make
+    pub turns: u32,
+    // the largest tile on the board when the game ends
+    pub max_tile: i32,
+    // score still to be gained
+    pub score: i32,
+}
+
+// What the players of a solved game fight over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Objective {
+    Turns,
+    MaxTile,
+    Score,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidObjective;
+
+impl FromStr for Objective {
+    type Err = InvalidObjective;
+
+    fn from_str(s: &str) -> Result<Objective, InvalidObjective> {
+        match s {
+            "turns" => Ok(Objective::Turns),
+            "tile" => Ok(Objective::MaxTile),
+            "score" => Ok(Objective::Score),
+            _ => Err(InvalidObjective),
+        }
+    }
+}
+
+impl fmt::Display for Objective {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Objective::Turns => write!(f, "turns"),
+            Objective::MaxTile => write!(f, "tile"),
+            Objective::Score => write!(f, "score"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TableError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    // the table's board is empty or too large to pack
+    BoardSize { rows: usize, cols: usize },
+    UnknownRules(String),
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableError::Io(e) => write!(f, "i/o error: {e}"),
+            TableError::BadMagic => write!(f, "not a solver table"),
+            TableError::UnsupportedVersion(v) => {
+                write!(f, "unsupported table version {v} (expected {VERSION})")
+            }
+            TableError::BoardSize { rows, cols } => {
+                write!(f, "can't use a table for a {rows}x{cols} board")
+            }
+            TableError::UnknownRules(name) => write!(f, "unknown rules {name:?}"),
+        }
+    }
+}
+
+impl From<io::Error> for TableError {
+    fn from(e: io::Error) -> TableError {
+        TableError::Io(e)
+    }
+}
+
+// The value of every position reachable on the rules' board
+#[derive(Debug, Clone)]
+pub struct Solution {
+    rules: Arc<dyn GameRules>,
+    packing: Packing,
+    table: HashMap<u64, Value>,
+}
+
+impl Solution {
+    // Panics if the positions don't fit in a key, which for the standard
+    // rules means more than MAX_CELLS cells. A standard 2x3 board is
+    // instant, 3x3 takes minutes and gigabytes (80 million positions),
+    // anything larger is out of reach.
+    pub fn solve(rules: Arc<dyn GameRules>) -> Solution {
+        let mut solution = Solution {
+            packing: Packing::new(&*rules).expect("Board too large to solve"),
+            rules,
+            table: HashMap::new(),
+        };
+        solution.solve_from(&State::start(solution.rules.clone()));
+        solution
+    }
+
+    // whether solve can take the rules
+    pub fn fits(rules: &dyn GameRules) -> bool {
+        Packing::new(rules).is_some()
+    }
+
+    pub fn rules(&self) -> &Arc<dyn GameRules> {
+        &self.rules
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rules.rows()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.rules.cols()
+    }
+
+    // number of positions solved, symmetric ones counted once
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    // the value of the game from the empty board
+    pub fn start(&self) -> Value {
+        self.value(&State::start(self.rules.clone())).unwrap()
+    }
+
+    // None for positions that can't be reached from the empty board,
+    // including those of other rules
+    pub fn value(&self, s: &State) -> Option<Value> {
+        let rules = s.rules();
+        if rules.name() != self.rules.name()
+            || (rules.rows(), rules.cols()) != (self.rows(), self.cols())
+        {
+            return None;
+        }
+        self.table.get(&self.packing.canonical_key(s)).copied()
+    }
+
+    // Games from the empty board only last as long as their tile sum can
+    // grow, a few hundred plies at most on these boards, so plain recursion
+    // is fine.
+    fn solve_from(&mut self, s: &State) -> Value {
+        let key = self.packing.canonical_key(s);
+        if let Some(&v) = self.table.get(&key) {
+            return v;
+        }
+        let v = if s.terminal() {
+            Value {
+                turns: 0,
+                max_tile: s.max_tile(),
+                score: 0,
+            }
+        } else {
+            let mut best: Option<Value> = None;
+            for m in s.moves() {
+                let child = match state::next_state(s, m) {
+                    Ok(child) => child,
+                    Err(_) => continue,
+                };
+                let v = self.solve_from(&child);
+                let v = Value {
+                    turns: v.turns,
+                    max_tile: v.max_tile,
+                    score: v.score + child.score() - s.score(),
+                };
+                best = Some(match best {
+                    None => v,
+                    Some(b) if s.next_to_move() == Role::Slider => Value {
+                        turns: b.turns.max(v.turns),
+                        max_tile: b.max_tile.max(v.max_tile),
+                        score: b.score.max(v.score),
+                    },
+                    Some(b) => Value {
+                        turns: b.turns.min(v.turns),
+                        max_tile: b.max_tile.min(v.max_tile),
+                        score: b.score.min(v.score),
+                    },
+                });
+            }
+            // the Placer always has an empty cell after a slide
+            let mut v = best.unwrap();
+            if s.next_to_move() == Role::Slider {
+                v.turns += 1;
+            }
+            v
+        };
+        self.table.insert(key, v);
+        v
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), TableError> {
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&[self.rows() as u8, self.cols() as u8])?;
+        let name = self.rules.name();
+        w.write_all(&[name.len() as u8])?;
+        w.write_all(name.as_bytes())?;
+        w.write_all(&(self.table.len() as u64).to_le_bytes())?;
+        for (key, v) in &self.table {
+            w.write_all(&key.to_le_bytes())?;
+            w.write_all(&v.turns.to_le_bytes())?;
+            w.write_all(&v.max_tile.to_le_bytes())?;
+            w.write_all(&v.score.to_le_bytes())?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Solution, TableError> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(TableError::BadMagic);
+        }
+        let version = u32::from_le_bytes(read_bytes(&mut r)?);
+        if version != VERSION {
+            return Err(TableError::UnsupportedVersion(version));
+        }
+        let [rows, cols] = read_bytes(&mut r)?.map(usize::from);
+        if rows == 0 || cols == 0 {
+            return Err(TableError::BoardSize { rows, cols });
+        }
+        let [len] = read_bytes(&mut r)?;
+        let mut name = vec![0; len as usize];
+        r.read_exact(&mut name)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let rules = rules::by_name(&name, rows, cols).ok_or(TableError::UnknownRules(name))?;
+        let packing = Packing::new(&*rules).ok_or(TableError::BoardSize { rows, cols })?;
+        let count = u64::from_le_bytes(read_bytes(&mut r)?);
+        let mut table = HashMap::new();
+        for _ in 0..count {
+            let key = u64::from_le_bytes(read_bytes(&mut r)?);
+            let v = Value {
+                turns: u32::from_le_bytes(read_bytes(&mut r)?),
+                max_tile: i32::from_le_bytes(read_bytes(&mut r)?),
+                score: i32::from_le_bytes(read_bytes(&mut r)?),
+            };
+            table.insert(key, v);
+        }
+        Ok(Solution {
+            rules,
+            packing,
+            table,
+        })
+    }
+}
+
+fn read_bytes<const N: usize, R: Read>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// For each symmetry, where the cell at (i, j) of the transformed grid comes
+// from, given the last row and column. The last four transpose, so they only
+// apply to square boards. They're in the order of rules::symmetries.
+type Transform = fn(usize, usize, usize, usize) -> (usize, usize);
+
+const TRANSFORMS: [Transform; 8] = [
+    |i, j, _, _| (i, j),
+    |i, j, r, _| (r - i, j),
+    |i, j, _, c| (i, c - j),
+    |i, j, r, c| (r - i, c - j),
+    |i, j, _, _| (j, i),
+    |i, j, _, c| (j, c - i),
+    |i, j, r, _| (r - j, i),
+    |i, j, r, c| (r - j, c - i),
+];
+
+// How tiles are packed into keys: each as its index in values plus one (0 for
+// empty, and values.len() + 1 for a wall where the rules have walls) in bits
+// bits, row-major, then the phase in phase_bits bits, the tiles placed this
+// turn in placed_bits bits and a bit for the side to move
+#[derive(Debug, Clone)]
+struct Packing {
+    values: Vec<i32>,
+    bits: u32,
+    // 0 unless the rules have a schedule of directions
+    phase_bits: u32,
+    // 0 unless the Placer drops several tiles per turn
+    placed_bits: u32,
+    // see rules::symmetries
+    symmetries: Vec<usize>,
+}
+
+impl Packing {
+    // None if the rules' positions don't fit in a u64
+    fn new(rules: &dyn GameRules) -> Option<Packing> {
+        let values = rules::tile_values(rules);
+        let codes = if rules.blocked().is_empty() && rules.walls() == 0 {
+            values.len()
+        } else {
+            values.len() + 1
+        };
+        let bits = usize::BITS - codes.leading_zeros();
+        let phase_bits = usize::BITS - (rules.phases() - 1).leading_zeros();
+        let placed_bits = usize::BITS - (rules.drops() - 1).leading_zeros();
+        let cells = (rules.rows() * rules.cols()) as u32;
+        (cells * bits + phase_bits + placed_bits < u64::BITS).then(|| Packing {
+            values,
+            bits,
+            phase_bits,
+            placed_bits,
+            symmetries: rules::symmetries(rules),
+        })
+    }
+
+    fn pack(&self, s: &State, transform: Transform) -> u64 {
+        let grid = s.grid();
+        let (r, c) = (grid.rows() - 1, grid.cols() - 1);
+        let mut key = 0;
+        for (i, j) in grid.coords() {
+            let (x, y) = transform(i, j, r, c);
+            let code = match grid[x][y] {
+                0 => 0,
+                state::WALL => self.values.len() + 1,
+                val => {
+                    self.values
+                        .binary_search(&val)
+                        .expect("Tile not in the rules")
+                        + 1
+                }
+            };
+            key = key << self.bits | code as u64;
+        }
+        key = key << self.phase_bits | s.phase() as u64;
+        key = key << self.placed_bits | s.placed() as u64;
+        key << 1 | (s.next_to_move() == Role::Slider) as u64
+    }
+
+    fn canonical_key(&self, s: &State) -> u64 {
+        self.symmetries
+            .iter()
+            .map(|&t| self.pack(s, TRANSFORMS[t]))
+            .min()
+            .unwrap()
+    }
+}
+
+// Plays perfectly on a solved board, for either role, going for the given
+// objective. Ties go to the first move in the usual move order. Panics on
+// positions the solution doesn't have, which includes other board sizes.
+pub struct Perfect {
+    solution: Arc<Solution>,
+    objective: Objective,
+}
+
+impl Perfect {
+    pub fn new(solution: Arc<Solution>, objective: Objective) -> Perfect {
+        Perfect {
+            solution,
+            objective,
+        }
+    }
+}
+
+impl Player for Perfect {
+    fn pick_move(&mut self, s: &State) -> Move {
+        let mut best: Option<(i64, Move)> = None;
+        for m in s.moves() {
+            let child = match state::next_state(s, m) {
+                Ok(child) => child,
+                Err(_) => continue,
+            };
+            let v = self
+                .solution
+                .value(&child)
+                .expect("Position not in the solution");
+            let value = match self.objective {
+                Objective::Turns => v.turns as i64,
+                Objective::MaxTile => v.max_tile as i64,
+                Objective::Score => (v.score + child.score() - s.score()) as i64,
+            };
+            // the Placer plays for the lowest value
+            let value = match s.next_to_move() {
+                Role::Slider => value,
+                Role::Placer => -value,
+            };
+            if best.is_none_or(|(b, _)| value > b) {
+                best = Some((value, m));
+            }
+        }
+        best.expect("No legal move").1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Standard;
+    use crate::Game;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn two_by_two() {
+        let solution = Solution::solve(Arc::new(Standard::new(2, 2)));
+        assert_eq!(
+            solution.start(),
+            Value {
+                turns: 4,
+                max_tile: 8,
+                score: 8,
+            }
+        );
+
+        let s = State::from_notation("2,0/2,0 s").unwrap();
+        assert_eq!(
+            solution.value(&s),
+            Some(Value {
+                turns: 4,
+                max_tile: 8,
+                score: 12,
+            })
+        );
+        assert!(solution.value(&State::empty(3, 3)).is_none());
+    }
+
+    #[test]
+    fn perfect_players_reach_the_solved_value() {
+        let solution = Arc::new(Solution::solve(Arc::new(Standard::new(2, 3))));
+        let v = solution.start();
+        assert_eq!(
+            v,
+            Value {
+                turns: 9,
+                max_tile: 16,
+                score: 40,
+            }
+        );
+        for objective in [Objective::Turns, Objective::MaxTile, Objective::Score] {
+            let mut g = Game::with_size(
+                Box::new(Perfect::new(solution.clone(), objective)),
+                Box::new(Perfect::new(solution.clone(), objective)),
+                2,
+                3,
+            );
+            let r = g.play();
+            match objective {
+                Objective::Turns => assert_eq!(r.slider_turns as u32, v.turns),
+                Objective::MaxTile => assert_eq!(r.max_tile, v.max_tile),
+                Objective::Score => assert_eq!(r.score, v.score),
+            }
+        }
+    }
+
+    #[test]
+    fn variants() {
+        // sliding sideways and up or down in turn, or facing two tiles a
+        // turn, the Slider doesn't last the 9 turns it does in the standard
+        // game
+        for name in ["standard+slides:LR,UD", "standard+drops:2"] {
+            let rules = rules::by_name(name, 2, 3).unwrap();
+            let solution = Arc::new(Solution::solve(rules.clone()));
+            let v = solution.start();
+            assert!(v.turns < 9, "{name}");
+            let mut g = Game::with_rules(
+                Box::new(Perfect::new(solution.clone(), Objective::Turns)),
+                Box::new(Perfect::new(solution.clone(), Objective::Turns)),
+                rules,
+            );
+            assert_eq!(g.play().slider_turns as u32, v.turns, "{name}");
+        }
+    }
+
+    #[test]
+    fn table_roundtrip() {
+        let path = env::temp_dir().join(format!("a2048-{}-table", std::process::id()));
+        let solution = Solution::solve(Arc::new(Standard::new(2, 3)));
+        solution.save(&path).unwrap();
+        let loaded = Solution::load(&path).unwrap();
+        assert_eq!((loaded.rows(), loaded.cols()), (2, 3));
+        assert_eq!(loaded.len(), solution.len());
+        assert_eq!(loaded.start(), solution.start());
+
+        fs::write(&path, b"not a table").unwrap();
+        assert!(matches!(Solution::load(&path), Err(TableError::BadMagic)));
+        fs::remove_file(&path).unwrap();
+    }
+}
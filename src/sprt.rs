@@ -0,0 +1,257 @@
+use crate::arena::{Arena, PlayerConfig};
+use crate::rules::{GameRules, Standard};
+use crate::state::Role;
+use crate::GameResult;
+use std::sync::Arc;
+
+// Pairs after which differences that never varied, as with deterministic
+// configs and opponent, end the test (see Sprt::flat_outcome)
+const FLAT_PAIRS: usize = 20;
+
+// Sequential probability ratio test on the difference in survival turns
+// between a candidate and a baseline config. Each pair of games plays both
+// against the same opponent on the same seeds, and the test stops once the
+// log-likelihood ratio of "the candidate is delta1 turns better" against
+// "it's delta0 turns better" leaves the bounds given by alpha and beta.
+// For the Placer, better means fewer turns.
+#[derive(Debug, Clone)]
+pub struct Sprt {
+    delta0: f64,
+    delta1: f64,
+    alpha: f64,
+    beta: f64,
+    max_pairs: Option<usize>,
+    threads: usize,
+    seed: u64,
+    rules: Arc<dyn GameRules>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SprtOutcome {
+    // the candidate is an improvement
+    Accepted,
+    Rejected,
+    // max_pairs ran out first, or FLAT_PAIRS pairs were all the same games
+    Inconclusive,
+}
+
+// The running statistics after each pair
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SprtStatus {
+    pub pairs: usize,
+    // candidate minus baseline, in the tested role's favour
+    pub mean_diff: f64,
+    pub std_dev: f64,
+    pub llr: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Sprt {
+    // alpha and beta default to 0.05
+    pub fn new(delta0: f64, delta1: f64) -> Sprt {
+        assert!(delta0 < delta1, "delta0 must be below delta1");
+        Sprt {
+            delta0,
+            delta1,
+            alpha: 0.05,
+            beta: 0.05,
+            max_pairs: None,
+            threads: 1,
+            seed: 0,
+            rules: Arc::new(Standard::default()),
+        }
+    }
+
+    // alpha: chance of accepting a candidate that's only delta0 better,
+    // beta: chance of rejecting one that's delta1 better
+    pub fn with_errors(mut self, alpha: f64, beta: f64) -> Sprt {
+        assert!(
+            alpha > 0.0 && alpha < 1.0 && beta > 0.0 && beta < 1.0,
+            "Error rates must be between 0 and 1"
+        );
+        self.alpha = alpha;
+        self.beta = beta;
+        self
+    }
+
+    pub fn with_max_pairs(mut self, max_pairs: Option<usize>) -> Sprt {
+        self.max_pairs = max_pairs;
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Sprt {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Sprt {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_size(self, rows: usize, cols: usize) -> Sprt {
+        self.with_rules(Arc::new(Standard::new(rows, cols)))
+    }
+
+    pub fn with_rules(mut self, rules: Arc<dyn GameRules>) -> Sprt {
+        self.rules = rules;
+        self
+    }
+
+    // Plays pairs of games, calling progress with the statistics after each
+    // pair, until the test decides or max_pairs is reached. The opponent
+    // takes the other role.
+    pub fn run(
+        &self,
+        role: Role,
+        baseline: &PlayerConfig,
+        candidate: &PlayerConfig,
+        opponent: &PlayerConfig,
+        mut progress: impl FnMut(&SprtStatus),
+    ) -> (SprtOutcome, SprtStatus) {
+        let lower = (self.beta / (1.0 - self.alpha)).ln();
+        let upper = ((1.0 - self.beta) / self.alpha).ln();
+        let mut diffs: Vec<f64> = Vec::new();
+        let mut status = SprtStatus {
+            pairs: 0,
+            mean_diff: 0.0,
+            std_dev: 0.0,
+            llr: 0.0,
+            lower,
+            upper,
+        };
+        loop {
+            // a batch of pairs on consecutive seeds, one per thread
+            let batch = match self.max_pairs {
+                Some(max) if diffs.len() >= max => return (SprtOutcome::Inconclusive, status),
+                Some(max) => self.threads.min(max - diffs.len()),
+                None => self.threads,
+            };
+            let arena = Arena::new(batch)
+                .with_threads(self.threads)
+                .with_seed(self.seed.wrapping_add(2 * diffs.len() as u64))
+                .with_rules(self.rules.clone());
+            let play = |config: &PlayerConfig| match role {
+                Role::Slider => arena.run_match(config, opponent).results,
+                Role::Placer => arena.run_match(opponent, config).results,
+            };
+            let turns = |r: &GameResult| r.slider_turns as f64;
+            for (b, c) in play(baseline).iter().zip(&play(candidate)) {
+                diffs.push(match role {
+                    Role::Slider => turns(c) - turns(b),
+                    Role::Placer => turns(b) - turns(c),
+                });
+                status = self.status(&diffs, lower, upper);
+                progress(&status);
+                if status.llr >= upper {
+                    return (SprtOutcome::Accepted, status);
+                }
+                if status.llr <= lower {
+                    return (SprtOutcome::Rejected, status);
+                }
+                if status.std_dev == 0.0 && status.pairs >= FLAT_PAIRS {
+                    return (self.flat_outcome(status.mean_diff), status);
+                }
+            }
+        }
+    }
+
+    // When every pair differed by diff. Identical games say nothing about
+    // the candidate, while a constant difference falls on the side of one
+    // hypothesis, unless it's right between them.
+    fn flat_outcome(&self, diff: f64) -> SprtOutcome {
+        let mid = (self.delta0 + self.delta1) / 2.0;
+        if diff == 0.0 || diff == mid {
+            SprtOutcome::Inconclusive
+        } else if diff > mid {
+            SprtOutcome::Accepted
+        } else {
+            SprtOutcome::Rejected
+        }
+    }
+
+    // Normal approximation with the sample variance. Until the differences
+    // vary there's nothing to go on, so the ratio stays at 0.
+    fn status(&self, diffs: &[f64], lower: f64, upper: f64) -> SprtStatus {
+        let n = diffs.len() as f64;
+        let mean = diffs.iter().sum::<f64>() / n;
+        let var = if diffs.len() > 1 {
+            diffs.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / (n - 1.0)
+        } else {
+            0.0
+        };
+        let llr = if var > 0.0 {
+            n * (self.delta1 - self.delta0) * (mean - (self.delta0 + self.delta1) / 2.0) / var
+        } else {
+            0.0
+        };
+        SprtStatus {
+            pairs: diffs.len(),
+            mean_diff: mean,
+            std_dev: var.sqrt(),
+            llr,
+            lower,
+            upper,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn llr() {
+        let sprt = Sprt::new(0.0, 10.0);
+        // mean 5 is right between the hypotheses
+        let s = sprt.status(&[0.0, 10.0], -1.0, 1.0);
+        assert_eq!((s.mean_diff, s.llr), (5.0, 0.0));
+        let s = sprt.status(&[10.0, 20.0], -1.0, 1.0);
+        assert_eq!(s.llr, 2.0 * 10.0 * 10.0 / 50.0);
+        assert_eq!(sprt.status(&[3.0, 3.0], -1.0, 1.0).llr, 0.0);
+
+        // constant differences are judged against the midpoint 5
+        assert_eq!(sprt.flat_outcome(0.0), SprtOutcome::Inconclusive);
+        assert_eq!(sprt.flat_outcome(5.0), SprtOutcome::Inconclusive);
+        assert_eq!(sprt.flat_outcome(3.0), SprtOutcome::Rejected);
+        assert_eq!(sprt.flat_outcome(-3.0), SprtOutcome::Rejected);
+        assert_eq!(sprt.flat_outcome(7.0), SprtOutcome::Accepted);
+    }
+
+    #[test]
+    fn decides_obvious_cases() {
+        let random = PlayerConfig::random();
+        let ai = PlayerConfig::ai(1);
+        let sprt = Sprt::new(0.0, 5.0).with_seed(5).with_max_pairs(Some(200));
+        let mut calls = 0;
+        let (outcome, status) = sprt.run(Role::Slider, &random, &ai, &ai, |_| calls += 1);
+        assert_eq!(outcome, SprtOutcome::Accepted);
+        assert_eq!(calls, status.pairs);
+        assert!(status.llr >= status.upper);
+
+        let (outcome, _) = sprt.run(Role::Placer, &ai, &random, &random, |_| {});
+        assert_eq!(outcome, SprtOutcome::Rejected);
+
+        // identical configs play identical games, which must not keep the
+        // test going without a limit
+        let sprt = Sprt::new(0.0, 5.0).with_seed(5);
+        let (outcome, status) = sprt.run(Role::Slider, &ai, &ai, &random, |_| {});
+        assert_eq!(outcome, SprtOutcome::Inconclusive);
+        assert_eq!((status.pairs, status.std_dev), (FLAT_PAIRS, 0.0));
+
+        // with both sides searching, every pair plays the same two games,
+        // here with the deeper Slider lasting longer every time
+        let deeper = PlayerConfig::ai(2);
+        let small = sprt.clone().with_size(3, 3);
+        let (outcome, status) = small.run(Role::Slider, &ai, &deeper, &ai, |_| {});
+        assert_eq!(outcome, SprtOutcome::Accepted);
+        assert_eq!((status.pairs, status.std_dev), (FLAT_PAIRS, 0.0));
+        assert!(status.mean_diff > 5.0);
+
+        let sprt = sprt.with_max_pairs(Some(2));
+        let (outcome, status) = sprt.run(Role::Slider, &random, &random, &ai, |_| {});
+        assert_eq!(outcome, SprtOutcome::Inconclusive);
+        assert_eq!(status.pairs, 2);
+    }
+}
@@ -1,12 +1,18 @@
+use crate::rules::{self, GameRules, Standard};
+use itertools::iproduct;
+use std::fmt;
+use std::ops::{Index, IndexMut};
+use std::str::FromStr;
+use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 pub enum Role {
     Slider,
     Placer,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Direction {
     Up,
     Down,
@@ -14,26 +20,169 @@ pub enum Direction {
     Right,
 }
 
-#[derive(Copy, Clone, Debug)]
+impl Direction {
+    // Same numbering as the web frontend: 0 = up, 1 = right, 2 = down, 3 = left
+    pub fn from_index(i: i32) -> Option<Direction> {
+        match i {
+            0 => Some(Direction::Up),
+            1 => Some(Direction::Right),
+            2 => Some(Direction::Down),
+            3 => Some(Direction::Left),
+            _ => None,
+        }
+    }
+
+    pub fn index(self) -> i32 {
+        match self {
+            Direction::Up => 0,
+            Direction::Right => 1,
+            Direction::Down => 2,
+            Direction::Left => 3,
+        }
+    }
+
+    // Where the direction points once the board is transformed by symmetry
+    // t: 0 is the identity, 1 flips the rows, 2 the columns and 3 both, and
+    // 4 to 7 do the same after transposing (square boards only). This is the
+    // order of ai::symmetries and the solver's transforms.
+    pub fn transformed(self, t: usize) -> Direction {
+        use Direction::*;
+        let [up, down, left, right] = match t {
+            0 => [Up, Down, Left, Right],
+            1 => [Down, Up, Left, Right],
+            2 => [Up, Down, Right, Left],
+            3 => [Down, Up, Right, Left],
+            4 => [Left, Right, Up, Down],
+            5 => [Left, Right, Down, Up],
+            6 => [Right, Left, Up, Down],
+            7 => [Right, Left, Down, Up],
+            _ => panic!("Invalid symmetry"),
+        };
+        match self {
+            Up => up,
+            Down => down,
+            Left => left,
+            Right => right,
+        }
+    }
+}
+
+pub const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Move {
     Slide(Direction),
     Place { x: usize, y: usize, val: i32 },
 }
 
-#[derive(Debug, PartialEq)]
+// A rows x cols grid of tiles, 0 for empty. grid[x] is row x, so cells are
+// read and written as grid[x][y] with x counting rows from the top.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Board {
+    rows: usize,
+    cols: usize,
+    cells: Vec<i32>,
+}
+
+impl Board {
+    // Panics on a board without cells
+    pub fn new(rows: usize, cols: usize) -> Board {
+        assert!(rows > 0 && cols > 0, "Empty board");
+        Board {
+            rows,
+            cols,
+            cells: vec![0; rows * cols],
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.rows && y < self.cols
+    }
+
+    // row-major
+    pub fn cells(&self) -> &[i32] {
+        &self.cells
+    }
+
+    pub fn coords(&self) -> impl Iterator<Item = (usize, usize)> {
+        iproduct!(0..self.rows, 0..self.cols)
+    }
+}
+
+impl Index<usize> for Board {
+    type Output = [i32];
+
+    fn index(&self, x: usize) -> &[i32] {
+        &self.cells[x * self.cols..(x + 1) * self.cols]
+    }
+}
+
+impl IndexMut<usize> for Board {
+    fn index_mut(&mut self, x: usize) -> &mut [i32] {
+        &mut self.cells[x * self.cols..(x + 1) * self.cols]
+    }
+}
+
+impl<const R: usize, const C: usize> From<[[i32; C]; R]> for Board {
+    fn from(rows: [[i32; C]; R]) -> Board {
+        Board {
+            rows: R,
+            cols: C,
+            cells: rows.iter().flatten().copied().collect(),
+        }
+    }
+}
+
+impl<const R: usize, const C: usize> PartialEq<[[i32; C]; R]> for Board {
+    fn eq(&self, other: &[[i32; C]; R]) -> bool {
+        self.rows == R && self.cols == C && self.cells.iter().eq(other.iter().flatten())
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct State {
-    grid: [[i32; 4]; 4],
+    grid: Board,
     next_to_move: Role,
     score: i32,
     terminal: bool,
+    // see GameRules::phases
+    phase: usize,
+    // tiles the Placer has placed so far this turn, see GameRules::drops
+    placed: usize,
+    rules: Arc<dyn GameRules>,
+}
+
+// rules are told apart by name, see GameRules::name
+impl PartialEq for State {
+    fn eq(&self, other: &State) -> bool {
+        self.grid == other.grid
+            && self.next_to_move == other.next_to_move
+            && self.score == other.score
+            && self.phase == other.phase
+            && self.placed == other.placed
+            && self.rules.name() == other.rules.name()
+    }
 }
 
-pub const INITIAL_STATE: State = State {
-    grid: [[0; 4]; 4],
-    next_to_move: Role::Placer,
-    score: 0,
-    terminal: false,
-};
+// a blocked cell, see GameRules::blocked and GameRules::walls
+pub const WALL: i32 = -1;
+
+// the classic board
+pub const ROWS: usize = 4;
+pub const COLS: usize = 4;
 
 pub const SLIDER_MOVES: [Move; 4] = [
     Move::Slide(Direction::Up),
@@ -42,46 +191,306 @@ pub const SLIDER_MOVES: [Move; 4] = [
     Move::Slide(Direction::Down),
 ];
 
-pub const PLACER_MOVES: [Move; 32] = [
-    Move::Place { x: 0, y: 0, val: 2 },
-    Move::Place { x: 0, y: 1, val: 2 },
-    Move::Place { x: 0, y: 2, val: 2 },
-    Move::Place { x: 0, y: 3, val: 2 },
-    Move::Place { x: 1, y: 0, val: 2 },
-    Move::Place { x: 1, y: 1, val: 2 },
-    Move::Place { x: 1, y: 2, val: 2 },
-    Move::Place { x: 1, y: 3, val: 2 },
-    Move::Place { x: 2, y: 0, val: 2 },
-    Move::Place { x: 2, y: 1, val: 2 },
-    Move::Place { x: 2, y: 2, val: 2 },
-    Move::Place { x: 2, y: 3, val: 2 },
-    Move::Place { x: 3, y: 0, val: 2 },
-    Move::Place { x: 3, y: 1, val: 2 },
-    Move::Place { x: 3, y: 2, val: 2 },
-    Move::Place { x: 3, y: 3, val: 2 },
-    Move::Place { x: 0, y: 0, val: 4 },
-    Move::Place { x: 0, y: 1, val: 4 },
-    Move::Place { x: 0, y: 2, val: 4 },
-    Move::Place { x: 0, y: 3, val: 4 },
-    Move::Place { x: 1, y: 0, val: 4 },
-    Move::Place { x: 1, y: 1, val: 4 },
-    Move::Place { x: 1, y: 2, val: 4 },
-    Move::Place { x: 1, y: 3, val: 4 },
-    Move::Place { x: 2, y: 0, val: 4 },
-    Move::Place { x: 2, y: 1, val: 4 },
-    Move::Place { x: 2, y: 2, val: 4 },
-    Move::Place { x: 2, y: 3, val: 4 },
-    Move::Place { x: 3, y: 0, val: 4 },
-    Move::Place { x: 3, y: 1, val: 4 },
-    Move::Place { x: 3, y: 2, val: 4 },
-    Move::Place { x: 3, y: 3, val: 4 },
-];
+// Text notation used by records and the command line: slides are U, D, L
+// or R, placements are <val>@<x>,<y> (e.g. 4@0,3), or W@<x>,<y> for a wall.
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Move::Slide(Direction::Up) => write!(f, "U"),
+            Move::Slide(Direction::Down) => write!(f, "D"),
+            Move::Slide(Direction::Left) => write!(f, "L"),
+            Move::Slide(Direction::Right) => write!(f, "R"),
+            Move::Place { x, y, val: WALL } => write!(f, "W@{x},{y}"),
+            Move::Place { x, y, val } => write!(f, "{val}@{x},{y}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidNotation;
+
+impl FromStr for Move {
+    type Err = InvalidNotation;
+
+    fn from_str(s: &str) -> Result<Move, InvalidNotation> {
+        let d = match s {
+            "U" | "u" => Some(Direction::Up),
+            "D" | "d" => Some(Direction::Down),
+            "L" | "l" => Some(Direction::Left),
+            "R" | "r" => Some(Direction::Right),
+            _ => None,
+        };
+        if let Some(d) = d {
+            return Ok(Move::Slide(d));
+        }
+        let (val, pos) = s.split_once('@').ok_or(InvalidNotation)?;
+        let (x, y) = pos.split_once(',').ok_or(InvalidNotation)?;
+        Ok(Move::Place {
+            x: x.parse().map_err(|_| InvalidNotation)?,
+            y: y.parse().map_err(|_| InvalidNotation)?,
+            val: parse_cell(val)?,
+        })
+    }
+}
+
+// a tile value or W for a wall
+fn parse_cell(s: &str) -> Result<i32, InvalidNotation> {
+    match s {
+        "W" | "w" => Ok(WALL),
+        _ => s
+            .parse()
+            .ok()
+            .filter(|&val| val >= 0)
+            .ok_or(InvalidNotation),
+    }
+}
 
 #[derive(Debug)]
 pub struct InvalidMove; // grid stayed the same
 
+#[derive(Debug, PartialEq)]
+pub enum InvalidState {
+    // tiles must be 0 (empty) or a power of two, at least 2, and walls
+    // where the rules have them
+    BadTile { x: usize, y: usize, val: i32 },
+    // the grid can't be the result of the previous ply
+    Unreachable,
+}
+
 impl State {
-    pub fn grid(&self) -> &[[i32; 4]; 4] {
+    // under the standard rules
+    pub fn new(grid: impl Into<Board>, next_to_move: Role, score: i32) -> State {
+        let grid = grid.into();
+        let rules = Arc::new(Standard::new(grid.rows, grid.cols));
+        State::new_with(grid, next_to_move, score, rules)
+    }
+
+    // Panics if the grid isn't the size of the rules' board
+    pub fn new_with(
+        grid: impl Into<Board>,
+        next_to_move: Role,
+        score: i32,
+        rules: Arc<dyn GameRules>,
+    ) -> State {
+        State::at_phase(grid.into(), next_to_move, score, rules, 0)
+    }
+
+    fn at_phase(
+        grid: Board,
+        next_to_move: Role,
+        score: i32,
+        rules: Arc<dyn GameRules>,
+        phase: usize,
+    ) -> State {
+        assert!(
+            (grid.rows, grid.cols) == (rules.rows(), rules.cols()),
+            "Grid doesn't fit the rules"
+        );
+        assert!(phase < rules.phases(), "Invalid phase");
+        let mut s = State {
+            grid,
+            next_to_move,
+            score,
+            terminal: false,
+            phase,
+            placed: 0,
+            rules,
+        };
+        s.terminal = s.won() || s.rules.terminal(&s);
+        s
+    }
+
+    // The same position at another phase of the rules' schedule. Panics
+    // unless phase < rules().phases().
+    pub fn with_phase(self, phase: usize) -> State {
+        let placed = self.placed;
+        State {
+            placed,
+            ..State::at_phase(self.grid, self.next_to_move, self.score, self.rules, phase)
+        }
+    }
+
+    // The same position with placed tiles already placed this turn. Panics
+    // unless placed < rules().drops(), and the Placer is to move if any were.
+    pub fn with_placed(self, placed: usize) -> State {
+        assert!(
+            placed < self.rules.drops() && (placed == 0 || self.next_to_move == Role::Placer),
+            "Invalid placed count"
+        );
+        State { placed, ..self }
+    }
+
+    // the start of a standard game: no tiles, the Placer to move
+    pub fn empty(rows: usize, cols: usize) -> State {
+        State::start(Arc::new(Standard::new(rows, cols)))
+    }
+
+    // the start of a game under rules, with walls on the blocked cells
+    pub fn start(rules: Arc<dyn GameRules>) -> State {
+        let mut grid = Board::new(rules.rows(), rules.cols());
+        for &(x, y) in rules.blocked() {
+            grid[x][y] = WALL;
+        }
+        State::new_with(grid, Role::Placer, 0, rules)
+    }
+
+    // Like new, but checks the position could occur in a real game: the
+    // Placer moves after a slide (or on the empty board), and the Slider
+    // moves after a 2 or 4 was placed on such a grid. See check.
+    pub fn from_grid(
+        grid: impl Into<Board>,
+        next_to_move: Role,
+        score: i32,
+    ) -> Result<State, InvalidState> {
+        let grid = grid.into();
+        let rules = Arc::new(Standard::new(grid.rows, grid.cols));
+        State::from_grid_with(grid, next_to_move, score, rules)
+    }
+
+    // from_grid under other rules, whose tiles and spawns are checked for
+    pub fn from_grid_with(
+        grid: impl Into<Board>,
+        next_to_move: Role,
+        score: i32,
+        rules: Arc<dyn GameRules>,
+    ) -> Result<State, InvalidState> {
+        let s = State::new_with(grid, next_to_move, score, rules);
+        s.check()?;
+        Ok(s)
+    }
+
+    // Whether the position could occur in a real game under its rules: the
+    // tiles are the rules' and the grid is what the previous plies leave.
+    // Under rules with several drops per turn, the Slider moves after all of
+    // them were placed, or fewer if they filled the board. Targets are made
+    // by slides, and nobody moves after that.
+    pub fn check(&self) -> Result<(), InvalidState> {
+        let (grid, rules) = (&self.grid, &self.rules);
+        let tiles = rules::tile_values(&**rules);
+        for (x, y) in grid.coords() {
+            let val = grid[x][y];
+            let blocked = rules.blocked().contains(&(x, y));
+            let ok = match val {
+                WALL => blocked || rules.walls() > 0,
+                _ => !blocked && (val == 0 || tiles.contains(&val)),
+            };
+            if !ok {
+                return Err(InvalidState::BadTile { x, y, val });
+            }
+        }
+        let placed_walls =
+            grid.cells.iter().filter(|&&val| val == WALL).count() - rules.blocked().len();
+        let full = !grid.cells.contains(&0);
+        let drops = rules.drops();
+        let reachable = placed_walls <= rules.walls()
+            && (!self.won() || (self.next_to_move == Role::Placer && self.placed == 0))
+            && match self.next_to_move {
+                Role::Placer => {
+                    (self.placed == 0 || !full) && after_drops(grid, self.placed, 0, &**rules)
+                }
+                Role::Slider => {
+                    (1..=drops).any(|n| (n == drops || full) && after_drops(grid, n, 0, &**rules))
+                }
+            };
+        if reachable {
+            Ok(())
+        } else {
+            Err(InvalidState::Unreachable)
+        }
+    }
+
+    // Position notation: rows top to bottom separated by '/', cells
+    // separated by ',' with 0 for empty and W for a wall, then the side to
+    // move ('s' or 'p')
+    // and optionally the score, e.g. "2,0,0,0/0,0,0,0/0,0,4,0/0,0,0,0 s 0".
+    // Under rules with a schedule of directions, the phase follows the
+    // score, then under rules with several drops per turn the tiles placed
+    // so far this turn. Each is 0 if left out, along with what follows.
+    // The board's size is given by the number of rows and cells.
+    pub fn to_notation(&self) -> String {
+        let rows: Vec<String> = (0..self.grid.rows)
+            .map(|x| {
+                self.grid[x]
+                    .iter()
+                    .map(|&v| match v {
+                        WALL => "W".to_string(),
+                        _ => v.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect();
+        let side = match self.next_to_move {
+            Role::Slider => 's',
+            Role::Placer => 'p',
+        };
+        let mut notation = format!("{} {} {}", rows.join("/"), side, self.score);
+        if self.rules.phases() > 1 {
+            notation += &format!(" {}", self.phase);
+        }
+        if self.rules.drops() > 1 {
+            notation += &format!(" {}", self.placed);
+        }
+        notation
+    }
+
+    // Parses to_notation's format; the position must pass from_grid
+    pub fn from_notation(notation: &str) -> Result<State, InvalidNotation> {
+        State::from_notation_with(notation, "standard")
+    }
+
+    // Parses a position under the rules called rules (see rules::by_name),
+    // on the board size the notation gives
+    pub fn from_notation_with(notation: &str, rules: &str) -> Result<State, InvalidNotation> {
+        let mut fields = notation.split_whitespace();
+        let rows: Vec<&str> = fields.next().ok_or(InvalidNotation)?.split('/').collect();
+        let cols = rows[0].split(',').count();
+        let mut grid = Board::new(rows.len(), cols);
+        for (i, row) in rows.iter().enumerate() {
+            let cells: Vec<&str> = row.split(',').collect();
+            if cells.len() != cols {
+                return Err(InvalidNotation);
+            }
+            for (j, cell) in cells.iter().enumerate() {
+                grid[i][j] = parse_cell(cell)?;
+            }
+        }
+        let next_to_move = match fields.next() {
+            Some("s") => Role::Slider,
+            Some("p") => Role::Placer,
+            _ => return Err(InvalidNotation),
+        };
+        let score = match fields.next() {
+            Some(score) => score.parse().map_err(|_| InvalidNotation)?,
+            None => 0,
+        };
+        let rules = rules::by_name(rules, grid.rows, grid.cols).ok_or(InvalidNotation)?;
+        // a count below limit, which is only a field if the limit isn't 1
+        let mut count = |limit: usize| {
+            if limit == 1 {
+                return Ok(0);
+            }
+            match fields.next() {
+                Some(n) => n.parse().ok().filter(|&n| n < limit).ok_or(InvalidNotation),
+                None => Ok(0),
+            }
+        };
+        let phase = count(rules.phases())?;
+        let placed = count(rules.drops())?;
+        if placed > 0 && next_to_move == Role::Slider {
+            return Err(InvalidNotation);
+        }
+        if fields.next().is_some() {
+            return Err(InvalidNotation);
+        }
+        let s = State::new_with(grid, next_to_move, score, rules)
+            .with_phase(phase)
+            .with_placed(placed);
+        s.check().map_err(|_| InvalidNotation)?;
+        Ok(s)
+    }
+
+    pub fn grid(&self) -> &Board {
         &self.grid
     }
 
@@ -93,94 +502,86 @@ impl State {
         self.score
     }
 
+    // whether the game is over, won or lost
     pub fn terminal(&self) -> bool {
         self.terminal
     }
-}
 
-fn slide_up(s: &State) -> Result<State, InvalidMove> {
-    let mut grid = [[0; 4]; 4];
-    let mut score = s.score;
-    for i in 0..4 {
-        let mut end = 0;
-        for j in 0..4 {
-            if s.grid[j][i] != 0 {
-                if grid[end][i] == 0 {
-                    grid[end][i] = s.grid[j][i];
-                } else if grid[end][i] == s.grid[j][i] {
-                    grid[end][i] *= 2;
-                    score += grid[end][i];
-                    end += 1;
-                } else {
-                    end += 1;
-                    grid[end][i] = s.grid[j][i];
-                }
-            }
-        }
+    // whether the Slider made the rules' target tile, see GameRules::target
+    pub fn won(&self) -> bool {
+        self.rules.target().is_some_and(|t| self.max_tile() >= t)
     }
 
-    if grid == s.grid {
-        Err(InvalidMove)
-    } else {
-        Ok(State {
-            grid,
-            next_to_move: Role::Placer,
-            score,
-            terminal: false,
-        })
+    pub fn max_tile(&self) -> i32 {
+        self.grid.cells.iter().copied().max().unwrap_or(0)
     }
-}
 
-fn slide_down(s: &State) -> Result<State, InvalidMove> {
-    let mut grid = [[0; 4]; 4];
-    let mut score = s.score;
-    for i in 0..4 {
-        let mut end = 0;
-        for j in 0..4 {
-            if s.grid[3 - j][i] != 0 {
-                if grid[3 - end][i] == 0 {
-                    grid[3 - end][i] = s.grid[3 - j][i];
-                } else if grid[3 - end][i] == s.grid[3 - j][i] {
-                    grid[3 - end][i] *= 2;
-                    score += grid[3 - end][i];
-                    end += 1;
-                } else {
-                    end += 1;
-                    grid[3 - end][i] = s.grid[3 - j][i];
-                }
-            }
-        }
+    pub fn rules(&self) -> &Arc<dyn GameRules> {
+        &self.rules
     }
 
-    if grid == s.grid {
-        Err(InvalidMove)
-    } else {
-        Ok(State {
-            grid,
-            next_to_move: Role::Placer,
-            score,
-            terminal: false,
-        })
+    // see GameRules::phases
+    pub fn phase(&self) -> usize {
+        self.phase
+    }
+
+    // tiles the Placer has placed so far this turn, see GameRules::drops
+    pub fn placed(&self) -> usize {
+        self.placed
+    }
+
+    // walls the Placer may still place
+    pub fn walls_left(&self) -> usize {
+        let walls = self.grid.cells.iter().filter(|&&val| val == WALL).count();
+        (self.rules.walls() + self.rules.blocked().len()).saturating_sub(walls)
+    }
+
+    // every move of the side to move, legal or not
+    pub fn moves(&self) -> Vec<Move> {
+        self.rules.moves(self.next_to_move)
     }
 }
 
-fn slide_left(s: &State) -> Result<State, InvalidMove> {
-    let mut grid = [[0; 4]; 4];
+// the classic 4x4 board, empty
+impl Default for State {
+    fn default() -> Self {
+        State::empty(ROWS, COLS)
+    }
+}
+
+fn slide(s: &State, d: Direction) -> Result<State, InvalidMove> {
+    if !s.rules.directions(s.phase).contains(&d) {
+        return Err(InvalidMove);
+    }
+    let mut grid = Board::new(s.grid.rows, s.grid.cols);
+    for (x, y) in grid.coords() {
+        if s.grid[x][y] == WALL {
+            grid[x][y] = WALL;
+        }
+    }
     let mut score = s.score;
-    for i in 0..4 {
+    for cells in segments(&s.grid, d) {
+        if s.rules.one_step() {
+            score += shift_line(&s.grid, &mut grid, &cells, &*s.rules);
+            continue;
+        }
         let mut end = 0;
-        for j in 0..4 {
-            if s.grid[i][j] != 0 {
-                if grid[i][end] == 0 {
-                    grid[i][end] = s.grid[i][j];
-                } else if grid[i][end] == s.grid[i][j] {
-                    grid[i][end] *= 2;
-                    score += grid[i][end];
-                    end += 1;
-                } else {
-                    end += 1;
-                    grid[i][end] = s.grid[i][j];
-                }
+        for &(x, y) in &cells {
+            let val = s.grid[x][y];
+            if val == 0 {
+                continue;
+            }
+            let (ex, ey) = cells[end];
+            if grid[ex][ey] == 0 {
+                grid[ex][ey] = val;
+            } else if let Some(merged) = s.rules.merge(grid[ex][ey], val) {
+                grid[ex][ey] = merged;
+                score += s.rules.merge_score(merged);
+                end += 1;
+            } else {
+                end += 1;
+                let (ex, ey) = cells[end];
+                grid[ex][ey] = val;
             }
         }
     }
@@ -188,110 +589,442 @@ fn slide_left(s: &State) -> Result<State, InvalidMove> {
     if grid == s.grid {
         Err(InvalidMove)
     } else {
-        Ok(State {
+        let phase = (s.phase + 1) % s.rules.phases();
+        Ok(State::at_phase(
             grid,
-            next_to_move: Role::Placer,
+            Role::Placer,
             score,
-            terminal: false,
-        })
+            s.rules.clone(),
+            phase,
+        ))
     }
 }
 
-fn slide_right(s: &State) -> Result<State, InvalidMove> {
-    let mut grid = [[0; 4]; 4];
-    let mut score = s.score;
-    for i in 0..4 {
-        let mut end = 0;
-        for j in 0..4 {
-            if s.grid[i][3 - j] != 0 {
-                if grid[i][3 - end] == 0 {
-                    grid[i][3 - end] = s.grid[i][3 - j];
-                } else if grid[i][3 - end] == s.grid[i][3 - j] {
-                    grid[i][3 - end] *= 2;
-                    score += grid[i][3 - end];
-                    end += 1;
-                } else {
-                    end += 1;
-                    grid[i][3 - end] = s.grid[i][3 - j];
-                }
-            }
+// Moves the segment through cells (listed from the edge) of from one cell
+// towards the edge into to, see GameRules::one_step. Returns the score.
+fn shift_line(
+    from: &Board,
+    to: &mut Board,
+    cells: &[(usize, usize)],
+    rules: &dyn GameRules,
+) -> i32 {
+    let mut vals: Vec<i32> = cells.iter().map(|&(x, y)| from[x][y]).collect();
+    let mut score = 0;
+    if let Some(k) = first_shift(&vals, rules) {
+        if vals[k - 1] == 0 {
+            vals[k - 1] = vals[k];
+        } else {
+            vals[k - 1] = rules.merge(vals[k - 1], vals[k]).unwrap();
+            score = rules.merge_score(vals[k - 1]);
         }
+        vals.copy_within(k + 1.., k);
+        *vals.last_mut().unwrap() = 0;
+    }
+    for (&(x, y), val) in cells.iter().zip(vals) {
+        to[x][y] = val;
     }
+    score
+}
 
-    if grid == s.grid {
-        Err(InvalidMove)
-    } else {
-        Ok(State {
-            grid,
-            next_to_move: Role::Placer,
-            score,
-            terminal: false,
-        })
+// In a one-step slide, the index of the tile nearest the edge (index 0) that
+// can move into an empty cell or merge
+fn first_shift(vals: &[i32], rules: &dyn GameRules) -> Option<usize> {
+    (1..vals.len()).find(|&k| {
+        vals[k] != 0 && (vals[k - 1] == 0 || rules.merge(vals[k - 1], vals[k]).is_some())
+    })
+}
+
+// No empty cell and no neighbours that merge, whichever slides into which.
+// Where there are walls, an empty cell only counts next to a tile, as walls
+// can shut it off from the rest.
+pub(crate) fn dead_grid<R: GameRules + ?Sized>(grid: &Board, rules: &R) -> bool {
+    let walls = grid.cells.contains(&WALL);
+    let moves = |a: i32, b: i32| match (a, b) {
+        (WALL, _) | (_, WALL) => false,
+        (0, 0) => false,
+        (0, _) | (_, 0) => walls,
+        _ => rules.merge(a, b).is_some() || rules.merge(b, a).is_some(),
+    };
+    grid.coords().all(|(x, y)| {
+        let val = grid[x][y];
+        (walls || val != 0)
+            && (x + 1 == grid.rows || !moves(val, grid[x + 1][y]))
+            && (y + 1 == grid.cols || !moves(val, grid[x][y + 1]))
+    })
+}
+
+// Whether grid is what n placements (of a spawn or a wall) leave after a
+// slide. Placements are taken off from cell index from on, so each set of
+// them is tried once.
+fn after_drops(grid: &Board, n: usize, from: usize, rules: &dyn GameRules) -> bool {
+    if n == 0 {
+        return after_slide(grid, rules.one_step());
     }
+    grid.coords().skip(from).enumerate().any(|(i, (x, y))| {
+        let val = grid[x][y];
+        let placed =
+            rules.spawns().contains(&val) || (val == WALL && !rules.blocked().contains(&(x, y)));
+        let mut prev = grid.clone();
+        prev[x][y] = 0;
+        placed && after_drops(&prev, n - 1, from + i + 1, rules)
+    })
 }
 
-fn dead_grid(grid: &[[i32; 4]; 4]) -> bool {
-    for i in 0..4 {
-        for j in 0..4 {
-            if grid[i][j] == 0 {
-                return false;
-            }
-        }
-        for j in 0..3 {
-            if grid[i][j] == grid[i][j + 1] {
-                return false;
-            }
-            if grid[j][i] == grid[j + 1][i] {
+// True for boards without tiles and for grids some slide could have
+// produced: every line segment between walls is packed against the same
+// edge, and at least one has room for a tile that could have slid into
+// place. After a one-step slide, only the far cell of a segment that moved
+// has to be empty.
+fn after_slide(grid: &Board, one_step: bool) -> bool {
+    if grid.cells.iter().all(|&val| val == 0 || val == WALL) {
+        return true;
+    }
+    if one_step {
+        return [
+            Direction::Up,
+            Direction::Down,
+            Direction::Left,
+            Direction::Right,
+        ]
+        .into_iter()
+        .any(|d| {
+            segments(grid, d).into_iter().any(|cells| {
+                let (x, y) = cells[cells.len() - 1];
+                grid[x][y] == 0 && cells.iter().any(|&(x, y)| grid[x][y] != 0)
+            })
+        });
+    }
+    [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ]
+    .into_iter()
+    .any(|d| {
+        let mut moved = false;
+        for cells in segments(grid, d) {
+            let vals: Vec<i32> = cells.into_iter().map(|(x, y)| grid[x][y]).collect();
+            let len = vals.iter().filter(|&&val| val != 0).count();
+            if vals[..len].contains(&0) {
                 return false;
             }
+            moved |= len > 0 && len < vals.len();
         }
-    }
-    true
+        moved
+    })
 }
 
 fn place(s: &State, x: usize, y: usize, val: i32) -> Result<State, InvalidMove> {
-    if s.grid[x][y] == 0 {
+    let allowed = match val {
+        WALL => s.walls_left() > 0,
+        _ => s.rules.spawns().contains(&val),
+    };
+    if s.grid.contains(x, y) && allowed && s.grid[x][y] == 0 {
         let mut grid = s.grid.clone();
         grid[x][y] = val;
-
-        Ok(State {
-            grid,
-            next_to_move: Role::Slider,
-            score: s.score,
-            terminal: dead_grid(&grid),
-        })
+        // the turn ends early once the board is full
+        let placed = s.placed + 1;
+        let more = placed < s.rules.drops() && grid.cells.contains(&0);
+        let next_to_move = if more { Role::Placer } else { Role::Slider };
+        let next = State::at_phase(grid, next_to_move, s.score, s.rules.clone(), s.phase);
+        Ok(if more { next.with_placed(placed) } else { next })
     } else {
         Err(InvalidMove)
     }
 }
 
 pub fn next_state(s: &State, m: Move) -> Result<State, InvalidMove> {
+    // players outside this crate can send anything
+    let role = match m {
+        Move::Slide(_) => Role::Slider,
+        Move::Place { .. } => Role::Placer,
+    };
+    // nothing is legal once the game is won
+    if role != s.next_to_move || s.won() {
+        return Err(InvalidMove);
+    }
     match m {
-        Move::Slide(d) => match d {
-            Direction::Up => slide_up(s),
-            Direction::Down => slide_down(s),
-            Direction::Left => slide_left(s),
-            Direction::Right => slide_right(s),
-        },
+        Move::Slide(d) => slide(s, d),
         Move::Place { x, y, val } => place(s, x, y, val),
     }
 }
 
-pub fn print_grid(grid: &[[i32; 4]; 4]) {
-    for i in 0..4 {
-        println!("-------------------------");
-        println!("|     |     |     |     |");
-        for j in 0..4 {
+// Where a single tile ends up after a slide, for animating the frontend.
+// Both tiles of a merge point at the same destination with merged = true.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TileMove {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    pub val: i32,
+    pub merged: bool,
+}
+
+// rows or columns, whichever the tiles slide along
+fn num_lines(grid: &Board, d: Direction) -> usize {
+    match d {
+        Direction::Up | Direction::Down => grid.cols,
+        Direction::Left | Direction::Right => grid.rows,
+    }
+}
+
+// cells of a row/column, ordered from the edge the tiles slide towards
+fn line_cells(grid: &Board, d: Direction, line: usize) -> Vec<(usize, usize)> {
+    let (rows, cols) = (grid.rows, grid.cols);
+    match d {
+        Direction::Up => (0..rows).map(|k| (k, line)).collect(),
+        Direction::Down => (0..rows).map(|k| (rows - 1 - k, line)).collect(),
+        Direction::Left => (0..cols).map(|k| (line, k)).collect(),
+        Direction::Right => (0..cols).map(|k| (line, cols - 1 - k)).collect(),
+    }
+}
+
+// The runs of cells between walls that tiles slide along, each ordered like
+// line_cells. A wall acts as the edge for the tiles behind it.
+fn segments(grid: &Board, d: Direction) -> Vec<Vec<(usize, usize)>> {
+    let mut segments = Vec::new();
+    for line in 0..num_lines(grid, d) {
+        let cells = line_cells(grid, d, line);
+        segments.extend(
+            cells
+                .split(|&(x, y)| grid[x][y] == WALL)
+                .filter(|cells| !cells.is_empty())
+                .map(|cells| cells.to_vec()),
+        );
+    }
+    segments
+}
+
+// Mirrors slide, but records tile movements instead of the resulting grid.
+// Tiles that don't move are included with from == to.
+pub fn tile_moves(grid: &Board, d: Direction, rules: &dyn GameRules) -> Vec<TileMove> {
+    let mut moves: Vec<TileMove> = Vec::new();
+    for cells in segments(grid, d) {
+        if rules.one_step() {
+            let vals: Vec<i32> = cells.iter().map(|&(x, y)| grid[x][y]).collect();
+            let shift = first_shift(&vals, rules).unwrap_or(vals.len());
+            let merged = shift < vals.len() && vals[shift - 1] != 0;
+            for (k, &val) in vals.iter().enumerate().filter(|&(_, &val)| val != 0) {
+                moves.push(TileMove {
+                    from: cells[k],
+                    to: cells[if k < shift { k } else { k - 1 }],
+                    val,
+                    merged: merged && (k == shift - 1 || k == shift),
+                });
+            }
+            continue;
+        }
+        let mut end = 0;
+        let mut end_val = 0;
+        for &(x, y) in &cells {
+            let val = grid[x][y];
+            if val == 0 {
+                continue;
+            }
+            let mut merged = false;
+            if end_val == 0 {
+                end_val = val;
+            } else if rules.merge(end_val, val).is_some() {
+                merged = true;
+                moves.last_mut().unwrap().merged = true;
+            } else {
+                end += 1;
+                end_val = val;
+            }
+            moves.push(TileMove {
+                from: (x, y),
+                to: cells[end],
+                val,
+                merged,
+            });
+            if merged {
+                end += 1;
+                end_val = 0;
+            }
+        }
+    }
+    moves
+}
+
+pub fn print_grid(grid: &Board) {
+    let border = "-".repeat(6 * grid.cols + 1);
+    let spacer = "|     ".repeat(grid.cols) + "|";
+    for i in 0..grid.rows {
+        println!("{border}");
+        println!("{spacer}");
+        for j in 0..grid.cols {
             if grid[i][j] > 0 {
                 print!("|{:^5}", grid[i][j]);
+            } else if grid[i][j] == WALL {
+                print!("|#####");
             } else {
                 print!("|     ");
             }
         }
         println!("|");
-        println!("|     |     |     |     |");
+        println!("{spacer}");
+    }
+    println!("{border}");
+}
+
+#[wasm_bindgen]
+pub struct WasmTileMove {
+    from_x: usize,
+    from_y: usize,
+    to_x: usize,
+    to_y: usize,
+    val: i32,
+    merged: bool,
+}
+
+#[wasm_bindgen]
+impl WasmTileMove {
+    pub fn from_x(&self) -> usize {
+        self.from_x
+    }
+    pub fn from_y(&self) -> usize {
+        self.from_y
+    }
+    pub fn to_x(&self) -> usize {
+        self.to_x
+    }
+    pub fn to_y(&self) -> usize {
+        self.to_y
+    }
+    pub fn val(&self) -> i32 {
+        self.val
+    }
+    pub fn merged(&self) -> bool {
+        self.merged
+    }
+}
+
+// The rules engine as seen from JavaScript. Moves that are illegal in the
+// current state are rejected (return false) and leave the game untouched.
+#[wasm_bindgen]
+pub struct WasmGame {
+    state: State,
+    last_moves: Vec<TileMove>,
+}
+
+impl Default for WasmGame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl WasmGame {
+    pub fn new() -> WasmGame {
+        WasmGame::with_size(ROWS, COLS)
+    }
+
+    pub fn with_size(rows: usize, cols: usize) -> WasmGame {
+        WasmGame {
+            state: State::empty(rows, cols),
+            last_moves: Vec::new(),
+        }
+    }
+
+    pub fn slide(&mut self, direction: i32) -> bool {
+        let d = match Direction::from_index(direction) {
+            Some(d) => d,
+            None => return false,
+        };
+        if self.state.next_to_move != Role::Slider || self.state.terminal {
+            return false;
+        }
+        match next_state(&self.state, Move::Slide(d)) {
+            Ok(s) => {
+                self.last_moves = tile_moves(&self.state.grid, d, &*self.state.rules);
+                self.state = s;
+                true
+            }
+            Err(InvalidMove) => false,
+        }
+    }
+
+    pub fn place(&mut self, x: usize, y: usize, val: i32) -> bool {
+        if !self.can_place(x, y, val) {
+            return false;
+        }
+        self.state = next_state(&self.state, Move::Place { x, y, val }).unwrap();
+        self.last_moves.clear();
+        true
+    }
+
+    pub fn can_slide(&self, direction: i32) -> bool {
+        match Direction::from_index(direction) {
+            Some(d) => {
+                self.state.next_to_move == Role::Slider
+                    && !self.state.terminal
+                    && next_state(&self.state, Move::Slide(d)).is_ok()
+            }
+            None => false,
+        }
+    }
+
+    pub fn can_place(&self, x: usize, y: usize, val: i32) -> bool {
+        self.state.next_to_move == Role::Placer
+            && self.state.grid.contains(x, y)
+            && self.state.rules.spawns().contains(&val)
+            && self.state.grid[x][y] == 0
+    }
+
+    // directions (see Direction::from_index) the slider may legally play
+    pub fn legal_slides(&self) -> Vec<i32> {
+        (0..4).filter(|&d| self.can_slide(d)).collect()
+    }
+
+    pub fn rows(&self) -> usize {
+        self.state.grid.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.state.grid.cols
+    }
+
+    // row-major grid, 0 for empty cells
+    pub fn grid(&self) -> Vec<i32> {
+        self.state.grid.cells.clone()
+    }
+
+    // None (undefined in JS) off the board
+    pub fn cell(&self, x: usize, y: usize) -> Option<i32> {
+        self.state
+            .grid
+            .contains(x, y)
+            .then(|| self.state.grid[x][y])
+    }
+
+    pub fn score(&self) -> i32 {
+        self.state.score
+    }
+
+    pub fn terminal(&self) -> bool {
+        self.state.terminal
+    }
+
+    pub fn slider_to_move(&self) -> bool {
+        self.state.next_to_move == Role::Slider
+    }
+
+    // tile movements of the most recent slide (empty after a placement)
+    pub fn num_tile_moves(&self) -> usize {
+        self.last_moves.len()
+    }
+
+    // None (undefined in JS) unless i < num_tile_moves()
+    pub fn tile_move(&self, i: usize) -> Option<WasmTileMove> {
+        self.last_moves.get(i).map(|m| WasmTileMove {
+            from_x: m.from.0,
+            from_y: m.from.1,
+            to_x: m.to.0,
+            to_y: m.to.1,
+            val: m.val,
+            merged: m.merged,
+        })
     }
-    println!("-------------------------");
 }
 
 #[cfg(test)]
@@ -300,7 +1033,7 @@ mod tests {
 
     #[test]
     fn place1() {
-        let s = INITIAL_STATE;
+        let s = State::default();
         let m = Move::Place { x: 1, y: 2, val: 2 };
         let s = next_state(&s, m).unwrap();
         assert_eq!(
@@ -309,18 +1042,17 @@ mod tests {
         );
         assert_eq!(
             s,
-            State {
-                grid: [[0, 0, 0, 0], [0, 0, 2, 0], [0, 0, 0, 0], [0, 0, 0, 0],],
-                next_to_move: Role::Slider,
-                score: 0,
-                terminal: false,
-            }
+            State::new(
+                [[0, 0, 0, 0], [0, 0, 2, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+                Role::Slider,
+                0
+            )
         );
     }
 
     #[test]
     fn slide_left() {
-        let s = INITIAL_STATE;
+        let s = State::default();
         let m = Move::Place { x: 1, y: 2, val: 2 };
         let s = next_state(&s, m).unwrap();
         let m = Move::Slide(Direction::Left);
@@ -334,12 +1066,11 @@ mod tests {
 
     #[test]
     fn slide_left2() {
-        let s = State {
-            grid: [[2, 2, 2, 2], [0, 4, 0, 4], [2, 0, 4, 2], [4, 4, 2, 2]],
-            next_to_move: Role::Slider,
-            score: 0,
-            terminal: false,
-        };
+        let s = State::new(
+            [[2, 2, 2, 2], [0, 4, 0, 4], [2, 0, 4, 2], [4, 4, 2, 2]],
+            Role::Slider,
+            0,
+        );
         let m = Move::Slide(Direction::Left);
         let s = next_state(&s, m).unwrap();
 
@@ -351,12 +1082,11 @@ mod tests {
 
     #[test]
     fn slide_left3() {
-        let s = State {
-            grid: [[2, 4, 2, 0], [2, 2, 4, 0], [2, 0, 4, 2], [0, 2, 4, 2]],
-            next_to_move: Role::Slider,
-            score: 0,
-            terminal: false,
-        };
+        let s = State::new(
+            [[2, 4, 2, 0], [2, 2, 4, 0], [2, 0, 4, 2], [0, 2, 4, 2]],
+            Role::Slider,
+            0,
+        );
         let m = Move::Slide(Direction::Left);
         let s = next_state(&s, m).unwrap();
 
@@ -368,12 +1098,11 @@ mod tests {
 
     #[test]
     fn slide_right() {
-        let s = State {
-            grid: [[2, 4, 2, 0], [2, 2, 4, 0], [2, 0, 2, 2], [0, 2, 4, 2]],
-            next_to_move: Role::Slider,
-            score: 0,
-            terminal: false,
-        };
+        let s = State::new(
+            [[2, 4, 2, 0], [2, 2, 4, 0], [2, 0, 2, 2], [0, 2, 4, 2]],
+            Role::Slider,
+            0,
+        );
         let m = Move::Slide(Direction::Right);
         let s = next_state(&s, m).unwrap();
 
@@ -385,12 +1114,11 @@ mod tests {
 
     #[test]
     fn slide_right2() {
-        let s = State {
-            grid: [[2, 2, 2, 2], [0, 4, 0, 4], [2, 0, 4, 2], [4, 4, 2, 2]],
-            next_to_move: Role::Slider,
-            score: 0,
-            terminal: false,
-        };
+        let s = State::new(
+            [[2, 2, 2, 2], [0, 4, 0, 4], [2, 0, 4, 2], [4, 4, 2, 2]],
+            Role::Slider,
+            0,
+        );
         let m = Move::Slide(Direction::Right);
         let s = next_state(&s, m).unwrap();
 
@@ -402,12 +1130,11 @@ mod tests {
 
     #[test]
     fn slide_up() {
-        let s = State {
-            grid: [[2, 2, 2, 2], [0, 4, 0, 4], [2, 0, 4, 2], [4, 4, 2, 2]],
-            next_to_move: Role::Slider,
-            score: 0,
-            terminal: false,
-        };
+        let s = State::new(
+            [[2, 2, 2, 2], [0, 4, 0, 4], [2, 0, 4, 2], [4, 4, 2, 2]],
+            Role::Slider,
+            0,
+        );
         let m = Move::Slide(Direction::Up);
         let s = next_state(&s, m).unwrap();
 
@@ -419,12 +1146,11 @@ mod tests {
 
     #[test]
     fn slide_up2() {
-        let s = State {
-            grid: [[0, 0, 0, 0], [0, 0, 2, 0], [4, 0, 0, 0], [4, 0, 2, 0]],
-            next_to_move: Role::Slider,
-            score: 0,
-            terminal: false,
-        };
+        let s = State::new(
+            [[0, 0, 0, 0], [0, 0, 2, 0], [4, 0, 0, 0], [4, 0, 2, 0]],
+            Role::Slider,
+            0,
+        );
         let m = Move::Slide(Direction::Up);
         let s = next_state(&s, m).unwrap();
 
@@ -436,12 +1162,11 @@ mod tests {
 
     #[test]
     fn slide_down() {
-        let s = State {
-            grid: [[2, 2, 2, 2], [0, 4, 0, 4], [2, 0, 4, 2], [4, 4, 2, 2]],
-            next_to_move: Role::Slider,
-            score: 0,
-            terminal: false,
-        };
+        let s = State::new(
+            [[2, 2, 2, 2], [0, 4, 0, 4], [2, 0, 4, 2], [4, 4, 2, 2]],
+            Role::Slider,
+            0,
+        );
         let m = Move::Slide(Direction::Down);
         let s = next_state(&s, m).unwrap();
 
@@ -451,50 +1176,419 @@ mod tests {
         );
     }
 
+    #[test]
+    fn slide_rectangular() {
+        let s = State::new([[2, 2, 4], [0, 4, 4]], Role::Slider, 0);
+        let slid = |d| next_state(&s, Move::Slide(d)).unwrap().grid;
+        assert_eq!(slid(Direction::Left), [[4, 4, 0], [8, 0, 0]]);
+        assert_eq!(slid(Direction::Right), [[0, 4, 4], [0, 0, 8]]);
+        assert_eq!(slid(Direction::Up), [[2, 2, 8], [0, 4, 0]]);
+        assert_eq!(slid(Direction::Down), [[0, 2, 0], [2, 4, 8]]);
+
+        assert!(dead_grid(
+            &Board::from([[2, 4, 8], [4, 8, 2]]),
+            &Standard::default()
+        ));
+        assert!(!dead_grid(
+            &Board::from([[2, 4, 8], [4, 8, 8]]),
+            &Standard::default()
+        ));
+        assert!(!dead_grid(
+            &Board::from([[2, 4, 8], [2, 8, 4]]),
+            &Standard::default()
+        ));
+    }
+
+    #[test]
+    fn slide_one_step() {
+        let rules = Arc::new(rules::Threes::new(4, 4));
+        let grid = [[1, 2, 3, 0], [0, 3, 3, 6], [3, 6, 12, 0], [2, 1, 0, 1]];
+        let s = State::new_with(grid, Role::Slider, 0, rules.clone());
+        let s = next_state(&s, Move::Slide(Direction::Left)).unwrap();
+        assert_eq!(
+            s.grid,
+            [[3, 3, 0, 0], [3, 3, 6, 0], [3, 6, 12, 0], [3, 0, 1, 0]]
+        );
+        assert_eq!(s.score, 6);
+
+        let moves = tile_moves(&Board::from(grid), Direction::Left, &*rules);
+        assert!(moves.contains(&TileMove {
+            from: (0, 1),
+            to: (0, 0),
+            val: 2,
+            merged: true
+        }));
+        assert!(moves.contains(&TileMove {
+            from: (1, 3),
+            to: (1, 2),
+            val: 6,
+            merged: false
+        }));
+        assert!(moves.contains(&TileMove {
+            from: (2, 2),
+            to: (2, 2),
+            val: 12,
+            merged: false
+        }));
+
+        // a one-step slide leaves a gap at the far end of a line
+        let grid = [[1, 2, 3, 6], [3, 1, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]];
+        assert!(State::from_grid_with(grid, Role::Placer, 0, rules.clone()).is_ok());
+        let grid = [[1, 2, 3, 6], [3, 1, 12, 6], [6, 3, 1, 2], [2, 6, 3, 1]];
+        assert_eq!(
+            State::from_grid_with(grid, Role::Placer, 0, rules).unwrap_err(),
+            InvalidState::Unreachable
+        );
+    }
+
     #[test]
     fn dead1() {
         let grid = [[2, 4, 8, 4], [256, 8, 4, 2], [4, 128, 2, 4], [2, 8, 64, 8]];
-        assert!(dead_grid(&grid));
+        assert!(dead_grid(&Board::from(grid), &Standard::default()));
     }
 
     #[test]
     fn dead2() {
         let grid = [[4, 16, 8, 4], [2, 4, 64, 16], [16, 32, 16, 8], [4, 2, 4, 2]];
-        assert!(dead_grid(&grid));
+        assert!(dead_grid(&Board::from(grid), &Standard::default()));
     }
 
     #[test]
     fn dead3() {
         let grid = [[4, 16, 8, 4], [2, 4, 64, 16], [16, 32, 0, 8], [4, 2, 4, 2]];
-        assert!(!dead_grid(&grid));
+        assert!(!dead_grid(&Board::from(grid), &Standard::default()));
     }
 
     #[test]
     fn dead4() {
         let grid = [[2, 4, 8, 4], [256, 8, 4, 2], [4, 128, 2, 4], [2, 2, 64, 8]];
-        assert!(!dead_grid(&grid));
+        assert!(!dead_grid(&Board::from(grid), &Standard::default()));
     }
 
     #[test]
     fn place2() {
-        let s = State {
-            grid: [[2, 4, 8, 4], [256, 8, 4, 2], [4, 128, 2, 4], [2, 0, 64, 8]],
-            next_to_move: Role::Placer,
-            score: 0,
-            terminal: false,
-        };
+        let s = State::new(
+            [[2, 4, 8, 4], [256, 8, 4, 2], [4, 128, 2, 4], [2, 0, 64, 8]],
+            Role::Placer,
+            0,
+        );
         let m = Move::Place { x: 3, y: 1, val: 4 };
         let s = next_state(&s, m).unwrap();
 
         assert_eq!(
             s,
-            State {
-                grid: [[2, 4, 8, 4], [256, 8, 4, 2], [4, 128, 2, 4], [2, 4, 64, 8],],
-                next_to_move: Role::Slider,
-                score: 0,
-                terminal: true,
+            State::new(
+                [[2, 4, 8, 4], [256, 8, 4, 2], [4, 128, 2, 4], [2, 4, 64, 8]],
+                Role::Slider,
+                0
+            )
+        );
+        assert!(s.terminal());
+    }
+
+    #[test]
+    fn from_grid_valid() {
+        let grid = [[0, 0, 0, 0], [0, 0, 2, 0], [0, 0, 0, 0], [0, 0, 0, 0]];
+        assert!(State::from_grid(grid, Role::Slider, 0).is_ok());
+        assert_eq!(
+            State::from_grid(grid, Role::Placer, 0),
+            Err(InvalidState::Unreachable)
+        );
+        let grid = [[8, 4, 0, 0], [2, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]];
+        assert!(State::from_grid(grid, Role::Placer, 12).is_ok());
+        let grid = [[8, 4, 0, 0], [2, 0, 0, 2], [0, 0, 0, 0], [0, 0, 0, 0]];
+        assert!(State::from_grid(grid, Role::Slider, 12).is_ok());
+        assert!(State::from_grid(Board::new(4, 4), Role::Placer, 0).is_ok());
+    }
+
+    #[test]
+    fn from_grid_invalid() {
+        let grid = [[8, 4, 0, 0], [2, 0, 0, 8], [0, 0, 0, 0], [0, 0, 0, 0]];
+        assert_eq!(
+            State::from_grid(grid, Role::Slider, 0),
+            Err(InvalidState::Unreachable)
+        );
+        let grid = [[3, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]];
+        assert_eq!(
+            State::from_grid(grid, Role::Slider, 0),
+            Err(InvalidState::BadTile { x: 0, y: 0, val: 3 })
+        );
+        // a full grid can't come out of a slide
+        let grid = [[2, 4, 2, 4], [4, 2, 4, 2], [2, 4, 2, 4], [4, 2, 4, 2]];
+        assert_eq!(
+            State::from_grid(grid, Role::Placer, 0),
+            Err(InvalidState::Unreachable)
+        );
+    }
+
+    #[test]
+    fn move_notation() {
+        for m in SLIDER_MOVES
+            .iter()
+            .chain(&Standard::default().placer_moves())
+        {
+            assert_eq!(m.to_string().parse::<Move>(), Ok(*m));
+        }
+        assert_eq!("4@0,3".parse(), Ok(Move::Place { x: 0, y: 3, val: 4 }));
+        assert_eq!("l".parse(), Ok(Move::Slide(Direction::Left)));
+        assert_eq!("4@03".parse::<Move>(), Err(InvalidNotation));
+        assert_eq!("up".parse::<Move>(), Err(InvalidNotation));
+    }
+
+    #[test]
+    fn position_notation() {
+        let s = State::new(
+            [[8, 4, 0, 0], [2, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+            Role::Placer,
+            12,
+        );
+        let notation = s.to_notation();
+        assert_eq!(notation, "8,4,0,0/2,0,0,0/0,0,0,0/0,0,0,0 p 12");
+        assert_eq!(State::from_notation(&notation), Ok(s));
+        assert_eq!(
+            State::from_notation("0,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 p").unwrap(),
+            State::default()
+        );
+        assert!(State::from_notation("8,4,0,0/2,0,0,0/0,0,0,0/0,0,0,0").is_err());
+        assert!(State::from_notation("8,4,0,0/2,0,0/0,0,0,0 p").is_err());
+
+        // other board sizes
+        let s = State::from_notation("8,4,0,0/2,0,0,0/0,0,0,0 p").unwrap();
+        assert_eq!((s.grid().rows(), s.grid().cols()), (3, 4));
+        assert_eq!(s.to_notation(), "8,4,0,0/2,0,0,0/0,0,0,0 p 0");
+        // not reachable
+        assert!(State::from_notation("0,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 s").is_err());
+    }
+
+    #[test]
+    fn walls() {
+        const W: i32 = WALL;
+        let name = "standard+blocked:1,1+walls:1";
+        let rules = rules::by_name(name, 4, 4).unwrap();
+        let s = State::start(rules.clone());
+        assert_eq!(s.grid[1][1], WALL);
+        assert_eq!(s.walls_left(), 1);
+        let wall = Move::Place { x: 0, y: 3, val: W };
+        assert_eq!(wall.to_string(), "W@0,3");
+        assert_eq!("W@0,3".parse(), Ok(wall));
+        let s = next_state(&s, wall).unwrap();
+        assert_eq!(s.walls_left(), 0);
+
+        let grid = [[2, 0, 2, W], [0, W, 2, 2], [4, 0, 0, 4], [0, 0, 0, 0]];
+        let s = State::new_with(grid, Role::Slider, 0, rules.clone());
+        let left = next_state(&s, Move::Slide(Direction::Left)).unwrap();
+        assert_eq!(
+            left.grid,
+            [[4, 0, 0, W], [0, W, 4, 0], [8, 0, 0, 0], [0, 0, 0, 0]]
+        );
+        assert_eq!(left.score, 16);
+        let up = next_state(&s, Move::Slide(Direction::Up)).unwrap();
+        assert_eq!(
+            up.grid,
+            [[2, 0, 4, W], [4, W, 0, 2], [0, 0, 0, 4], [0, 0, 0, 0]]
+        );
+        // both walls are down, so only a spawn goes on an empty cell
+        assert_eq!(left.walls_left(), 0);
+        assert!(next_state(&left, Move::Place { x: 0, y: 1, val: W }).is_err());
+        assert!(next_state(&left, Move::Place { x: 0, y: 1, val: 2 }).is_ok());
+        let grid = [[4, 0, 0, 0], [0, W, 4, 0], [8, 0, 0, 0], [0, 0, 0, 0]];
+        let s = State::new_with(grid, Role::Placer, 16, rules.clone());
+        assert_eq!(s.walls_left(), 1);
+        assert!(next_state(&s, Move::Place { x: 0, y: 1, val: W }).is_ok());
+
+        let notation = left.to_notation();
+        assert_eq!(notation, "4,0,0,W/0,W,4,0/8,0,0,0/0,0,0,0 p 16");
+        assert_eq!(State::from_notation_with(&notation, name), Ok(left));
+        assert!(State::from_notation(&notation).is_err());
+        let grid = [[2, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]];
+        assert_eq!(
+            State::from_grid_with(grid, Role::Slider, 0, rules).unwrap_err(),
+            InvalidState::BadTile { x: 1, y: 1, val: 0 }
+        );
+    }
+
+    #[test]
+    fn slide_schedule() {
+        let name = "standard+slides:DLR,UDL";
+        let rules = rules::by_name(name, 4, 4).unwrap();
+        let grid = [[0, 0, 0, 0], [2, 4, 2, 4], [4, 2, 4, 2], [2, 4, 2, 4]];
+        // only up moves a tile, and the first turn has no up
+        let s = State::new_with(grid, Role::Slider, 0, rules.clone());
+        assert_eq!(s.phase(), 0);
+        assert!(s.terminal());
+        assert!(next_state(&s, Move::Slide(Direction::Up)).is_err());
+        let s = s.with_phase(1);
+        assert!(!s.terminal());
+        assert_eq!(
+            s.to_notation(),
+            format!("{} s 0 1", "0,0,0,0/2,4,2,4/4,2,4,2/2,4,2,4")
+        );
+        assert_eq!(
+            State::from_notation_with(&s.to_notation(), name),
+            Ok(s.clone())
+        );
+        assert!(State::from_notation_with("0,0,0,0/2,4,2,4/4,2,4,2/2,4,2,4 s 0 2", name).is_err());
+
+        // the phase moves on with every slide, but not with placements
+        let up = next_state(&s, Move::Slide(Direction::Up)).unwrap();
+        assert_eq!(up.phase(), 0);
+        let s = next_state(&up, Move::Place { x: 3, y: 0, val: 2 }).unwrap();
+        assert_eq!(s.phase(), 0);
+        assert!(next_state(&s, Move::Slide(Direction::Up)).is_err());
+        let down = next_state(&s, Move::Slide(Direction::Down)).unwrap();
+        assert_eq!(down.phase(), 1);
+
+        // a lone tile can slide every way, but only the phase's ways are legal
+        let grid = [[0, 0, 0, 0], [0, 2, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]];
+        let s = State::new_with(grid, Role::Slider, 0, rules.clone());
+        for phase in 0..2 {
+            for d in DIRECTIONS {
+                let legal = next_state(&s.clone().with_phase(phase), Move::Slide(d)).is_ok();
+                assert_eq!(legal, rules.directions(phase).contains(&d), "{phase} {d:?}");
             }
+        }
+    }
+
+    #[test]
+    fn drops() {
+        let name = "standard+drops:2";
+        let rules = rules::by_name(name, 2, 2).unwrap();
+        let s = State::start(rules.clone());
+        let s = next_state(&s, Move::Place { x: 0, y: 0, val: 2 }).unwrap();
+        assert_eq!((s.next_to_move(), s.placed()), (Role::Placer, 1));
+        assert_eq!(s.to_notation(), "2,0/0,0 p 0 1");
+        assert_eq!(
+            State::from_notation_with("2,0/0,0 p 0 1", name),
+            Ok(s.clone())
+        );
+        let s = next_state(&s, Move::Place { x: 1, y: 1, val: 4 }).unwrap();
+        assert_eq!((s.next_to_move(), s.placed()), (Role::Slider, 0));
+        assert_eq!(State::from_notation_with("2,0/0,4 s", name), Ok(s));
+
+        // the turn ends early on a full board
+        let s = State::from_notation_with("2,4/4,0 p", name).unwrap();
+        let s = next_state(&s, Move::Place { x: 1, y: 1, val: 2 }).unwrap();
+        assert_eq!((s.next_to_move(), s.placed()), (Role::Slider, 0));
+        assert_eq!(State::from_notation_with("2,4/4,2 s", name), Ok(s));
+
+        for notation in [
+            "2,0/0,0 s",
+            "2,0/0,0 s 0 1",
+            "2,4/4,2 p 0 1",
+            "2,0/0,0 p 0 2",
+            "2,0/0,4 p 0 1 0",
+        ] {
+            assert!(
+                State::from_notation_with(notation, name).is_err(),
+                "{notation}"
+            );
+        }
+        assert!(State::from_notation("2,0,0,0/0,0,0,0/0,0,0,0/0,0,0,0 p 0 1").is_err());
+    }
+
+    #[test]
+    fn target() {
+        let name = "standard+target:8";
+        let s = State::from_notation_with("4,4,0/2,0,0/0,0,0 s", name).unwrap();
+        assert!(!s.won() && !s.terminal());
+        let s = next_state(&s, Move::Slide(Direction::Left)).unwrap();
+        assert!(s.won() && s.terminal());
+        assert_eq!(s.grid(), &[[8, 0, 0], [2, 0, 0], [0, 0, 0]]);
+        assert!(next_state(&s, Move::Place { x: 2, y: 2, val: 2 }).is_err());
+        assert_eq!(
+            State::from_notation_with("8,0,0/2,0,0/0,0,0 p 8", name),
+            Ok(s)
+        );
+        // a won game isn't played on
+        assert!(State::from_notation_with("8,0,0/2,0,0/0,0,2 s", name).is_err());
+        assert!(!State::from_notation("8,0,0/2,0,0/0,0,2 s").unwrap().won());
+    }
+
+    #[test]
+    fn tile_moves_left() {
+        let grid = [[2, 2, 2, 0], [0, 0, 0, 4], [2, 4, 0, 0], [0, 0, 0, 0]];
+        let moves = tile_moves(&Board::from(grid), Direction::Left, &Standard::default());
+        assert_eq!(
+            moves,
+            vec![
+                TileMove {
+                    from: (0, 0),
+                    to: (0, 0),
+                    val: 2,
+                    merged: true
+                },
+                TileMove {
+                    from: (0, 1),
+                    to: (0, 0),
+                    val: 2,
+                    merged: true
+                },
+                TileMove {
+                    from: (0, 2),
+                    to: (0, 1),
+                    val: 2,
+                    merged: false
+                },
+                TileMove {
+                    from: (1, 3),
+                    to: (1, 0),
+                    val: 4,
+                    merged: false
+                },
+                TileMove {
+                    from: (2, 0),
+                    to: (2, 0),
+                    val: 2,
+                    merged: false
+                },
+                TileMove {
+                    from: (2, 1),
+                    to: (2, 1),
+                    val: 4,
+                    merged: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tile_moves_down() {
+        let grid = [[4, 0, 0, 0], [4, 0, 0, 0], [4, 0, 0, 0], [4, 0, 0, 0]];
+        let moves = tile_moves(&Board::from(grid), Direction::Down, &Standard::default());
+        let targets: Vec<_> = moves.iter().map(|m| (m.from, m.to)).collect();
+        assert_eq!(
+            targets,
+            vec![
+                ((3, 0), (3, 0)),
+                ((2, 0), (3, 0)),
+                ((1, 0), (2, 0)),
+                ((0, 0), (2, 0))
+            ]
         );
+        assert!(moves.iter().all(|m| m.merged));
+    }
+
+    #[test]
+    fn wasm_game() {
+        let mut g = WasmGame::new();
+        assert!(!g.slider_to_move());
+        assert!(!g.slide(3));
+        assert!(!g.place(0, 0, 8));
+        assert!(g.place(0, 0, 2));
+        assert!(!g.place(0, 1, 2));
+        assert_eq!(g.legal_slides(), vec![1, 2]);
+        assert!(!g.slide(3));
+        assert!(g.slide(1));
+        assert_eq!(g.cell(0, 3), Some(2));
+        assert_eq!(g.cell(4, 0), None);
+        assert_eq!(g.num_tile_moves(), 1);
+        assert_eq!(g.tile_move(0).unwrap().to_y(), 3);
+        assert!(g.tile_move(1).is_none());
+        assert!(g.place(0, 2, 2));
+        assert!(g.slide(1));
+        assert_eq!(g.score(), 4);
+        assert_eq!(g.grid()[3], 4);
+        assert!(!g.terminal());
     }
 }
 
@@ -1,12 +1,22 @@
 extern crate web_sys;
 
 // A macro to provide `println!(..)`-style syntax for `console.log` logging.
+#[cfg(target_arch = "wasm32")]
 macro_rules! log {
     ( $( $t:tt )* ) => {
         web_sys::console::log_1(&format!( $( $t )* ).into());
     }
 }
 
+// There is no console outside the browser (calling into web_sys panics), so
+// native builds only type-check the arguments.
+#[cfg(not(target_arch = "wasm32"))]
+macro_rules! log {
+    ( $( $t:tt )* ) => {
+        let _ = format_args!( $( $t )* );
+    }
+}
+
 pub(crate) use log;
 
 pub fn set_panic_hook() {
@@ -0,0 +1,71 @@
+use adversarial_2048::external::{External, ExternalError};
+use adversarial_2048::random::Random;
+use adversarial_2048::state::{Role, State};
+use adversarial_2048::{Game, Player, Termination};
+use std::time::Duration;
+
+const MOCK: &str = env!("CARGO_BIN_EXE_mock-engine");
+
+const TIMEOUT: Duration = Duration::from_millis(300);
+
+fn mock(mode: &str) -> External {
+    External::spawn_with_timeout(MOCK, &[mode.to_string()], TIMEOUT).unwrap()
+}
+
+#[test]
+fn plays_a_game() {
+    let engine = mock("first");
+    assert_eq!(engine.name(), Some("mock-engine"));
+    let mut g = Game::new(Box::new(engine), Box::new(Random::new(1)));
+    let r = g.play();
+    assert_eq!(r.termination, Termination::NoSlides);
+
+    let mut g = Game::new(Box::new(Random::new(1)), Box::new(mock("first")));
+    assert_eq!(g.play().termination, Termination::NoSlides);
+}
+
+#[test]
+fn misbehaving_engines_forfeit() {
+    for mode in ["hang", "crash", "illegal", "garbage"] {
+        let mut g = Game::new(Box::new(Random::new(1)), Box::new(mock(mode)));
+        let r = g.play();
+        assert_eq!(
+            r.termination,
+            Termination::IllegalMove(Role::Placer),
+            "{mode}"
+        );
+        assert!(r.moves.is_empty());
+    }
+
+    let mut engine = mock("hang");
+    engine.pick_move(&State::default());
+    assert!(matches!(engine.error(), Some(ExternalError::Timeout)));
+    let mut engine = mock("crash");
+    engine.pick_move(&State::default());
+    assert!(matches!(engine.error(), Some(ExternalError::Exited)));
+    let mut engine = mock("garbage");
+    engine.pick_move(&State::default());
+    assert!(matches!(engine.error(), Some(ExternalError::Protocol(_))));
+}
+
+#[test]
+fn handshake_timeout() {
+    assert!(matches!(
+        External::spawn_with_timeout(MOCK, &["silent".to_string()], TIMEOUT),
+        Err(ExternalError::Timeout)
+    ));
+    assert!(matches!(
+        External::spawn("/nonexistent/engine", &[]),
+        Err(ExternalError::Spawn(_))
+    ));
+}
+
+#[test]
+fn against_our_engine() {
+    let args = ["engine".to_string(), "--depth".to_string(), "2".to_string()];
+    let engine = External::spawn(env!("CARGO_BIN_EXE_adversarial-2048"), &args).unwrap();
+    assert_eq!(engine.name(), Some("adversarial-2048"));
+    let mut g = Game::new(Box::new(Random::new(2)), Box::new(engine));
+    let r = g.play();
+    assert_eq!(r.termination, Termination::NoSlides);
+}